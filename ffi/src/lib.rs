@@ -0,0 +1,4 @@
+//! libautotiling_rs_ffi, the layout decision of autotiling-rs as a C library. The functions are
+//! those of the `ffi` feature of autotiling-rs, see its src/ffi.rs.
+
+pub use autotiling_rs::ffi::*;
@@ -0,0 +1,502 @@
+use std::path::PathBuf;
+
+use clap::{ArgEnum, Parser, ValueHint};
+use serde::Deserialize;
+
+/// Which container's aspect ratio drives the split decision
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RatioTarget {
+    Focused,
+    Parent,
+}
+
+/// Which container the split command is issued on
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitTarget {
+    /// the focused window, so the split applies to its parent
+    Parent,
+    /// the outermost container below the workspace
+    Outermost,
+}
+
+/// What to do when the ratio is equal to the threshold
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TieBreak {
+    /// keep the current layout of the parent
+    Keep,
+    Splith,
+    Splitv,
+}
+
+/// A layout a --ratio-curve bucket maps to
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    Splith,
+    Splitv,
+    Tabbed,
+}
+
+/// How the layout for the focused window is picked
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// compare the aspect ratio of the window with --ratio
+    Ratio,
+    /// alternate the split direction with every level of nesting
+    Alternate,
+    /// alternate the split direction with every window on the workspace
+    Spiral,
+    /// the first window on the workspace on the left, all others stacked on the right
+    MasterStack,
+    /// split the parent container along its longer side, like i3's manual BSP habits
+    BspParent,
+}
+
+/// How decisions are logged to stdout
+#[derive(ArgEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// a readable line per decision
+    Text,
+    /// `real_ratio threshold layout`, with the skip reason as a single word instead of the layout
+    /// and `-` for the ratios when it was skipped before they were known
+    Minimal,
+}
+
+/// The shells --generate-completions writes a script for
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The command line. The default is an empty command line, without looking at the environment.
+#[derive(Parser, Default)]
+#[clap(version, author, about)]
+pub struct Cli {
+    /// Activate autotiling only on this workspace. More than one workspace may be specified.
+    #[clap(long, short = 'w')]
+    pub workspace: Vec<i32>,
+
+    /// How to pick the layout [default: ratio]
+    #[clap(long, arg_enum, env = "AUTOTILING_MODE")]
+    pub mode: Option<Mode>,
+
+    /// Use a different --mode on a workspace, given by number or name, e.g. `3=spiral`. May be given more than once.
+    #[clap(long, value_name = "WORKSPACE=MODE")]
+    pub workspace_mode: Vec<String>,
+
+    /// Split vertically when the window's height/width ratio is above this value [default: 0.4]
+    #[clap(long, short = 'r', env = "AUTOTILING_RATIO")]
+    pub ratio: Option<f32>,
+
+    /// Measure the ratio without title bars and borders.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub exclude_decorations: Option<bool>,
+
+    /// Swap the split directions, i.e. split horizontally where we would split vertically and the other way round.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub reverse: Option<bool>,
+
+    /// Swap the split directions on this output only, or not on it with --reverse. May be given more than once.
+    #[clap(long, value_name = "NAME")]
+    pub reverse_output: Vec<String>,
+
+    /// Measure the aspect ratio on the focused window or on its parent container [default: focused]
+    #[clap(long, arg_enum)]
+    pub ratio_target: Option<RatioTarget>,
+
+    /// Only manage the container with this id, or the children of it. May be given more than once.
+    #[clap(long, value_name = "ID")]
+    pub manage_con_id: Vec<i64>,
+
+    /// Do nothing when any container around the focused window is tabbed or stacked.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub skip_under_tabbed: Option<bool>,
+
+    /// Do nothing while any window on the focused output is fullscreen across all outputs.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub pause_on_global_fullscreen: Option<bool>,
+
+    /// Leave windows alone while they keep the screen from going idle, e.g. a playing video.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub pause_on_idle_inhibit: Option<bool>,
+
+    /// Leave workspaces with a single window alone instead of always splitting them horizontally.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub no_single_window_override: Option<bool>,
+
+    /// Leave the layout unchanged while the height/width ratio is between --deadzone-low and --deadzone-high.
+    #[clap(long, value_name = "RATIO")]
+    pub deadzone_low: Option<f32>,
+
+    /// Upper bound of the deadzone, see --deadzone-low.
+    #[clap(long, value_name = "RATIO")]
+    pub deadzone_high: Option<f32>,
+
+    /// Only manage outputs with these connector types, e.g. --output-type DP,HDMI
+    #[clap(long, value_name = "TYPE", use_value_delimiter = true)]
+    pub output_type: Vec<String>,
+
+    /// Do nothing while one of these binding modes is active, e.g. --pause-in-modes resize,move
+    #[clap(long, value_name = "MODE", use_value_delimiter = true)]
+    pub pause_in_modes: Vec<String>,
+
+    /// Do nothing while the screen is locked, that is while --lock-app is running. Only works on Linux, where the running processes are listed in /proc.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub pause_when_locked: Option<bool>,
+
+    /// Process name of the screen locker [default: swaylock]
+    #[clap(long, value_name = "NAME")]
+    pub lock_app: Option<String>,
+
+    /// Every this many milliseconds re-check the focused window even without any event, 0 disables this.
+    #[clap(long, value_name = "MS")]
+    pub poll_ms: Option<u64>,
+
+    /// Every this many milliseconds check that sway still answers and reconnect if not, 0 disables this. Without --ipc-timeout-ms, requests then time out after as long.
+    #[clap(long, value_name = "MS")]
+    pub keep_alive_ms: Option<u64>,
+
+    /// Give up on a request to sway after this many milliseconds and reconnect, so that a stalled sway can not block us forever, 0 waits forever.
+    #[clap(long, value_name = "MS")]
+    pub ipc_timeout_ms: Option<u64>,
+
+    /// Check the command line and the config files, print the resulting settings and exit.
+    #[clap(long)]
+    pub check_config: bool,
+
+    /// Windows whose app_id (or X11 class) matches this pattern are swallowing terminals, see --swallow-delay-ms.
+    #[clap(long, value_name = "REGEX")]
+    pub swallow_apps: Option<String>,
+
+    /// Wait this long before relaying out a swallowing window, 0 leaves them alone entirely.
+    #[clap(long, value_name = "MS")]
+    pub swallow_delay_ms: Option<u64>,
+
+    /// When the focused window has no size or its ratio is right at the threshold, fetch the tree again after this long.
+    #[clap(long, value_name = "MS")]
+    pub stale_retry_ms: Option<u64>,
+
+    /// Relayout the focused window after it was resized with a key binding.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub relayout_on_resize: Option<bool>,
+
+    /// Ignore window events that are most likely caused by the command we just issued.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub no_act_on_own_commands: Option<bool>,
+
+    /// Look at the tree again when the same window gets focused twice in a row. By default we assume nothing changed.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub no_skip_same_focus: Option<bool>,
+
+    /// Remember the layouts we set for a few seconds and don't set them again, even if the tree sway reports does not show them yet.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub container_layout_cache: Option<bool>,
+
+    /// Look at the same window at most once per this many milliseconds, no matter how many focus events it causes.
+    #[clap(long, value_name = "MS")]
+    pub per_container_ms: Option<u64>,
+
+    /// Keep what we know about the containers of at most this many workspaces, forgetting the least recently laid out one first. Workspaces that sway removes are forgotten right away. [default: 100]
+    #[clap(long, value_name = "N")]
+    pub max_workspaces_tracked: Option<u64>,
+
+    /// Wait this many milliseconds after a window appears before laying it out, many apps only settle on their size after starting.
+    #[clap(long, value_name = "MS")]
+    pub new_window_grace_ms: Option<u64>,
+
+    /// Only send layout commands when a new window appears. Focusing a window decides on its layout, which is applied once a window opens next to it. --poll-ms and --relayout-on-resize issue no commands.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub relayout_only_before_new: Option<bool>,
+
+    /// Ignore this many window events after every (re)connect.
+    #[clap(long, value_name = "N")]
+    pub warmup_events: Option<u64>,
+
+    /// Relayout every tiling window on every workspace once connected at startup.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub relayout_all_on_start: Option<bool>,
+
+    /// Print a summary of what the daemon did when it is stopped by SIGINT or SIGTERM.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub stats_on_exit: Option<bool>,
+
+    /// Wait for one focus event, print the decision the daemon would make and exit.
+    #[clap(long)]
+    pub self_test: bool,
+
+    /// Explain step by step how the focused window would be laid out and exit without changing anything.
+    #[clap(long)]
+    pub why: bool,
+
+    /// Read a tree as printed by `swaymsg -t get_tree` from stdin and print the decision for it instead of connecting to sway. Combine with --why for the reasoning.
+    #[clap(long)]
+    pub from_stdin: bool,
+
+    /// Print the decision for every event of a recording instead of connecting to sway. Every line of the file is a JSON object with the `time` in seconds, the window `event`, the `tree` sway reported and optionally the id of the `container` the event is about.
+    #[clap(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    pub simulate: Option<PathBuf>,
+
+    /// Split the focused window's parent or the outermost container below the workspace [default: parent]
+    #[clap(long, arg_enum)]
+    pub split_target: Option<SplitTarget>,
+
+    /// Skip over split containers that only hold a single child and split the container around them instead.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub collapse_single_child: Option<bool>,
+
+    /// Split horizontally regardless of the ratio while the parent container has fewer than this many children.
+    #[clap(long, value_name = "N")]
+    pub splith_below_count: Option<usize>,
+
+    /// Split vertically regardless of the ratio once the parent container has more than this many children.
+    #[clap(long, value_name = "N")]
+    pub splitv_above_count: Option<usize>,
+
+    /// What to do when the ratio equals --ratio [default: keep]
+    #[clap(long, arg_enum)]
+    pub tie_break: Option<TieBreak>,
+
+    /// Pick the layout from height/width ratio ranges instead of comparing with --ratio, e.g. `0:splith,1.2:splitv,3:tabbed`. Each layout applies from its ratio up to the next one.
+    #[clap(long, value_name = "CURVE")]
+    pub ratio_curve: Option<String>,
+
+    /// Learn which way every app usually gets split and lean that way when a ratio is close to the threshold.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub learn_app_layouts: Option<bool>,
+
+    /// Share of an app's decisions that must agree before --learn-app-layouts leans its way [default: 0.75]
+    #[clap(long, value_name = "SHARE")]
+    pub learn_confidence: Option<f32>,
+
+    /// Use the height/width ratio of the focused output as the threshold instead of --ratio.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub auto_ratio: Option<bool>,
+
+    /// Like --auto-ratio, but only for this output. May be given more than once, other outputs use --ratio.
+    #[clap(long, value_name = "NAME")]
+    pub auto_ratio_output: Vec<String>,
+
+    /// Take the output shape for --auto-ratio from its resolution in pixels instead of its logical size. The two only differ by the rounding of the logical size of scaled outputs.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub use_physical_size: Option<bool>,
+
+    /// Switch the parent container to tabbed once its width/height ratio exceeds this value.
+    #[clap(long, value_name = "RATIO")]
+    pub tabbed_threshold_ratio: Option<f32>,
+
+    /// Split the only window of a workspace vertically instead of horizontally on outputs whose width/height ratio exceeds this value.
+    #[clap(long, value_name = "RATIO")]
+    pub ultrawide_threshold: Option<f32>,
+
+    /// Fetch the tree again after every command and warn if sway did not apply it.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub double_check: Option<bool>,
+
+    /// Send the commands of a relayout pass (e.g. on SIGUSR2) to sway in a single message.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub batch_commands: Option<bool>,
+
+    /// Focus the previously focused window again if a layout change moved the focus.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub preserve_focus: Option<bool>,
+
+    /// Report on stderr why no relayout happened, the first time each reason comes up.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub explain_startup: Option<bool>,
+
+    /// Log every decision to stdout in this format.
+    #[clap(long, arg_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Append every decision to this CSV file.
+    #[clap(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    pub decisions_csv: Option<PathBuf>,
+
+    /// Shell command to run after every relayout. It gets $AT_LAYOUT, $AT_WORKSPACE and $AT_APPID in its environment.
+    #[clap(long, value_name = "COMMAND")]
+    pub on_relayout: Option<String>,
+
+    /// Show a desktop notification when errors keep piling up, e.g. because sway is gone.
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        multiple_values = false,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub notify_on_error: Option<bool>,
+
+    /// Shell command --notify-on-error runs, it gets the last error in $AT_ERROR [default: notify-send autotiling-rs "$AT_ERROR"]
+    #[clap(long, value_name = "COMMAND")]
+    pub notify_command: Option<String>,
+
+    /// Remember the layouts we chose in this file and restore them on startup. Configured containers get an `at:state:` mark.
+    #[clap(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    pub state_file: Option<PathBuf>,
+
+    /// Print a completion script for this shell and exit.
+    #[clap(long, arg_enum, value_name = "SHELL", hide = true)]
+    pub generate_completions: Option<CompletionShell>,
+}
@@ -0,0 +1,73 @@
+//! Time as seen by the daemon. Everything that waits for or schedules something asks a
+//! [`Clock`], so that the timing can be driven by hand instead of by the wall clock.
+
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+    /// The time of day, for timestamps meant for people
+    fn system_time(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when it is advanced, sleeping advances it right away. Its time of
+/// day starts at the Unix epoch.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Cell<Instant>,
+    system_time: Cell<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Cell::new(Instant::now()),
+            system_time: Cell::new(SystemTime::UNIX_EPOCH),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+        self.system_time.set(self.system_time.get() + duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_time.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
@@ -0,0 +1,226 @@
+//! Completion scripts for --generate-completions, written from the arguments clap knows about.
+//! They complete the options and the values of the ones with a fixed set of values or a path.
+
+use std::io::{self, Write};
+
+use clap::{Command, ValueHint};
+
+use crate::cli::CompletionShell;
+
+/// What an option takes after it
+enum Value<'a> {
+    /// nothing, or a `=value` that has to be typed out
+    None,
+    /// one of these
+    OneOf(Vec<String>),
+    Path,
+    /// anything, there is nothing to complete but its name
+    Any(&'a str),
+}
+
+struct Opt<'a> {
+    long: Option<&'a str>,
+    short: Option<char>,
+    /// the first line of the help
+    help: &'a str,
+    value: Value<'a>,
+    /// whether it may be given more than once
+    repeats: bool,
+}
+
+impl Opt<'_> {
+    fn names(&self) -> Vec<String> {
+        let short = self.short.map(|short| format!("-{}", short));
+        let long = self.long.map(|long| format!("--{}", long));
+        short.into_iter().chain(long).collect()
+    }
+}
+
+/**
+ * The visible options of `cmd`
+ */
+fn options<'help>(cmd: &Command<'help>) -> Vec<Opt<'help>> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && (arg.get_long().is_some() || arg.get_short().is_some()))
+        .map(|arg| {
+            let value = if !arg.is_takes_value_set() || arg.is_require_equals_set() {
+                Value::None
+            } else if let Some(values) = arg.get_possible_values() {
+                Value::OneOf(
+                    values
+                        .iter()
+                        .filter(|value| !value.is_hide_set())
+                        .map(|value| value.get_name().to_string())
+                        .collect(),
+                )
+            } else if matches!(
+                arg.get_value_hint(),
+                ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+            ) {
+                Value::Path
+            } else {
+                let name = arg
+                    .get_value_names()
+                    .and_then(|names| names.first().copied());
+                Value::Any(name.unwrap_or("VALUE"))
+            };
+            Opt {
+                long: arg.get_long(),
+                short: arg.get_short(),
+                help: arg
+                    .get_help()
+                    .unwrap_or_default()
+                    .lines()
+                    .next()
+                    .unwrap_or_default(),
+                value,
+                repeats: arg.is_multiple_occurrences_set(),
+            }
+        })
+        .collect()
+}
+
+/**
+ * Write the completion script for `shell` of `cmd`
+ */
+pub fn generate(shell: CompletionShell, cmd: &Command, out: &mut impl Write) -> io::Result<()> {
+    let options = options(cmd);
+    let bin = cmd.get_name();
+    match shell {
+        CompletionShell::Bash => bash(&options, bin, out),
+        CompletionShell::Zsh => zsh(&options, bin, out),
+        CompletionShell::Fish => fish(&options, bin, out),
+    }
+}
+
+fn bash(options: &[Opt], bin: &str, out: &mut impl Write) -> io::Result<()> {
+    let function = format!("_{}", bin.replace('-', "_"));
+    writeln!(out, "{}() {{", function)?;
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(out, "    case \"$prev\" in")?;
+    for opt in options {
+        let complete = match &opt.value {
+            Value::None => continue,
+            Value::OneOf(values) => format!("compgen -W \"{}\" -- \"$cur\"", values.join(" ")),
+            Value::Path => "compgen -f -- \"$cur\"".to_string(),
+            Value::Any(_) => String::new(),
+        };
+        writeln!(out, "        {})", opt.names().join("|"))?;
+        if complete.is_empty() {
+            writeln!(out, "            COMPREPLY=()")?;
+        } else {
+            writeln!(out, "            COMPREPLY=($({}))", complete)?;
+        }
+        writeln!(out, "            return 0")?;
+        writeln!(out, "            ;;")?;
+    }
+    writeln!(out, "    esac")?;
+    let names: Vec<String> = options.iter().flat_map(Opt::names).collect();
+    writeln!(
+        out,
+        "    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+        names.join(" ")
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "complete -F {} {}", function, bin)
+}
+
+fn zsh(options: &[Opt], bin: &str, out: &mut impl Write) -> io::Result<()> {
+    // the help goes in single quotes and square brackets
+    let escape = |text: &str| {
+        text.replace('\'', "'\\''")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:")
+    };
+    writeln!(out, "#compdef {}", bin)?;
+    writeln!(out)?;
+    writeln!(out, "_arguments \\")?;
+    for opt in options {
+        let value = match &opt.value {
+            Value::None => String::new(),
+            Value::OneOf(values) => format!(": :({})", values.join(" ")),
+            Value::Path => ":PATH:_files".to_string(),
+            Value::Any(name) => format!(":{}:", name),
+        };
+        for name in opt.names() {
+            // a long option takes its value after a `=` as well
+            let equals = name.starts_with("--") && !value.is_empty();
+            writeln!(
+                out,
+                "    '{}{}{}[{}]{}' \\",
+                if opt.repeats { "*" } else { "" },
+                name,
+                if equals { "=" } else { "" },
+                escape(opt.help),
+                value
+            )?;
+        }
+    }
+    writeln!(out, "    && return 0")
+}
+
+fn fish(options: &[Opt], bin: &str, out: &mut impl Write) -> io::Result<()> {
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('\'', "\\'");
+    for opt in options {
+        write!(out, "complete -c {}", bin)?;
+        if let Some(short) = opt.short {
+            write!(out, " -s {}", short)?;
+        }
+        if let Some(long) = opt.long {
+            write!(out, " -l {}", long)?;
+        }
+        write!(out, " -d '{}'", escape(opt.help))?;
+        match &opt.value {
+            Value::None => {}
+            Value::OneOf(values) => write!(out, " -x -a '{}'", values.join(" "))?,
+            Value::Path => write!(out, " -r -F")?,
+            Value::Any(_) => write!(out, " -x")?,
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{ArgEnum, CommandFactory};
+
+    use super::*;
+    use crate::cli::Cli;
+
+    fn script(shell: CompletionShell) -> String {
+        let mut out = Vec::new();
+        generate(shell, &Cli::command(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn every_shell_gets_a_script() {
+        for &shell in CompletionShell::value_variants() {
+            let script = script(shell);
+            assert!(script.contains("autotiling-rs"), "{:?}", shell);
+            assert!(script.contains("ratio-target"), "{:?}", shell);
+            assert!(script.contains("help"), "{:?}", shell);
+            // hidden options are not completed
+            assert!(!script.contains("generate-completions"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn values_are_completed() {
+        let bash = script(CompletionShell::Bash);
+        assert!(bash.contains("--mode)\n            COMPREPLY=($(compgen -W \"ratio alternate spiral master-stack bsp-parent\" -- \"$cur\"))"));
+        assert!(bash.contains("--state-file)\n            COMPREPLY=($(compgen -f -- \"$cur\"))"));
+        // a boolean setting only takes `=false`, the next word is not its value
+        assert!(!bash.contains("--double-check)"));
+
+        let fish = script(CompletionShell::Fish);
+        assert!(fish.contains(
+            "-l log-format -d 'Log every decision to stdout in this format' -x -a 'text minimal'"
+        ));
+        let zsh = script(CompletionShell::Zsh);
+        assert!(zsh.contains("'*-w[Activate autotiling only on this workspace. More than one workspace may be specified]:WORKSPACE:' \\"));
+    }
+}
@@ -0,0 +1,872 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+use swayipc::Node;
+
+use clap::ArgEnum;
+
+use crate::cli::{Cli, Layout, LogFormat, Mode, RatioTarget, SplitTarget, TieBreak};
+use crate::pattern::Pattern;
+
+const DEFAULT_RATIO: f32 = 0.4;
+
+/// System wide defaults, read first unless $AUTOTILING_SYSTEM_CONFIG names another file
+const SYSTEM_CONFIG: &str = "/etc/autotiling-rs/config.toml";
+
+/**
+ * Fully resolved configuration the daemon runs with
+ */
+#[derive(Debug)]
+pub struct Settings {
+    pub mode: Mode,
+    /// --mode overrides, keyed by workspace number or name
+    pub workspace_modes: Vec<(String, Mode)>,
+    pub ratio: f32,
+    /// where `ratio` came from, for --why
+    pub ratio_source: &'static str,
+    /// workspace numbers we manage, all of them if empty
+    pub workspaces: Vec<i32>,
+    /// connector types of the outputs we manage, all of them if empty
+    pub output_types: Vec<String>,
+    pub ratio_target: RatioTarget,
+    pub exclude_decorations: bool,
+    pub reverse: bool,
+    /// outputs on which `reverse` is flipped
+    pub reverse_outputs: Vec<String>,
+    pub tabbed_threshold_ratio: Option<f32>,
+    pub ultrawide_threshold: Option<f32>,
+    pub pause_on_global_fullscreen: bool,
+    pub pause_on_idle_inhibit: bool,
+    pub skip_under_tabbed: bool,
+    pub no_single_window_override: bool,
+    pub on_relayout: Option<String>,
+    pub notify_on_error: bool,
+    pub notify_command: String,
+    pub state_file: Option<PathBuf>,
+    pub explain_startup: bool,
+    pub log_format: Option<LogFormat>,
+    pub decisions_csv: Option<PathBuf>,
+    pub double_check: bool,
+    pub batch_commands: bool,
+    pub preserve_focus: bool,
+    pub poll_ms: u64,
+    pub keep_alive_ms: u64,
+    pub ipc_timeout_ms: u64,
+    pub warmup_events: u64,
+    pub relayout_all_on_start: bool,
+    pub no_act_on_own_commands: bool,
+    pub no_skip_same_focus: bool,
+    pub container_layout_cache: bool,
+    pub per_container_ms: u64,
+    pub max_workspaces_tracked: u64,
+    pub new_window_grace_ms: u64,
+    pub relayout_only_before_new: bool,
+    pub relayout_on_resize: bool,
+    pub stale_retry_ms: u64,
+    /// Inclusive range of ratios for which we keep the current layout
+    pub deadzone: Option<(f32, f32)>,
+    pub split_target: SplitTarget,
+    pub collapse_single_child: bool,
+    pub pause_in_modes: Vec<String>,
+    pub pause_when_locked: bool,
+    pub lock_app: String,
+    pub manage_con_id: Vec<i64>,
+    pub splith_below_count: Option<usize>,
+    pub splitv_above_count: Option<usize>,
+    pub tie_break: TieBreak,
+    /// (lowest ratio, layout) pairs sorted by the ratio, see --ratio-curve
+    pub ratio_curve: Option<Vec<(f32, Layout)>>,
+    pub learn_app_layouts: bool,
+    pub learn_confidence: f32,
+    pub auto_ratio: bool,
+    /// Outputs that derive the ratio like --auto-ratio, when it is not set for all of them
+    pub auto_ratio_outputs: Vec<String>,
+    pub use_physical_size: bool,
+    pub stats_on_exit: bool,
+    pub swallow_apps: Option<Pattern>,
+    pub swallow_delay_ms: u64,
+}
+
+/**
+ * Contents of a single config file. Every key is optional, so that a file only overrides what
+ * it actually mentions. The keys are named the same as the command line options.
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    mode: Option<Mode>,
+    workspace_mode: Option<Vec<String>>,
+    ratio: Option<f32>,
+    workspace: Option<Vec<i32>>,
+    output_type: Option<Vec<String>>,
+    ratio_target: Option<RatioTarget>,
+    exclude_decorations: Option<bool>,
+    reverse: Option<bool>,
+    reverse_output: Option<Vec<String>>,
+    tabbed_threshold_ratio: Option<f32>,
+    ultrawide_threshold: Option<f32>,
+    pause_on_global_fullscreen: Option<bool>,
+    pause_on_idle_inhibit: Option<bool>,
+    skip_under_tabbed: Option<bool>,
+    no_single_window_override: Option<bool>,
+    on_relayout: Option<String>,
+    notify_on_error: Option<bool>,
+    notify_command: Option<String>,
+    state_file: Option<PathBuf>,
+    explain_startup: Option<bool>,
+    log_format: Option<LogFormat>,
+    decisions_csv: Option<PathBuf>,
+    double_check: Option<bool>,
+    batch_commands: Option<bool>,
+    preserve_focus: Option<bool>,
+    poll_ms: Option<u64>,
+    keep_alive_ms: Option<u64>,
+    ipc_timeout_ms: Option<u64>,
+    warmup_events: Option<u64>,
+    relayout_all_on_start: Option<bool>,
+    no_act_on_own_commands: Option<bool>,
+    no_skip_same_focus: Option<bool>,
+    container_layout_cache: Option<bool>,
+    per_container_ms: Option<u64>,
+    max_workspaces_tracked: Option<u64>,
+    new_window_grace_ms: Option<u64>,
+    relayout_only_before_new: Option<bool>,
+    relayout_on_resize: Option<bool>,
+    stale_retry_ms: Option<u64>,
+    deadzone_low: Option<f32>,
+    deadzone_high: Option<f32>,
+    split_target: Option<SplitTarget>,
+    collapse_single_child: Option<bool>,
+    pause_in_modes: Option<Vec<String>>,
+    pause_when_locked: Option<bool>,
+    lock_app: Option<String>,
+    manage_con_id: Option<Vec<i64>>,
+    splith_below_count: Option<usize>,
+    splitv_above_count: Option<usize>,
+    tie_break: Option<TieBreak>,
+    ratio_curve: Option<String>,
+    learn_app_layouts: Option<bool>,
+    learn_confidence: Option<f32>,
+    auto_ratio: Option<bool>,
+    auto_ratio_output: Option<Vec<String>>,
+    use_physical_size: Option<bool>,
+    stats_on_exit: Option<bool>,
+    swallow_apps: Option<String>,
+    swallow_delay_ms: Option<u64>,
+}
+
+impl FileConfig {
+    /**
+     * Layer `other` on top of `self`, values set in `other` win
+     */
+    fn merge(self, other: FileConfig) -> FileConfig {
+        FileConfig {
+            mode: other.mode.or(self.mode),
+            workspace_mode: other.workspace_mode.or(self.workspace_mode),
+            ratio: other.ratio.or(self.ratio),
+            workspace: other.workspace.or(self.workspace),
+            output_type: other.output_type.or(self.output_type),
+            ratio_target: other.ratio_target.or(self.ratio_target),
+            exclude_decorations: other.exclude_decorations.or(self.exclude_decorations),
+            reverse: other.reverse.or(self.reverse),
+            reverse_output: other.reverse_output.or(self.reverse_output),
+            tabbed_threshold_ratio: other.tabbed_threshold_ratio.or(self.tabbed_threshold_ratio),
+            ultrawide_threshold: other.ultrawide_threshold.or(self.ultrawide_threshold),
+            pause_on_global_fullscreen: other
+                .pause_on_global_fullscreen
+                .or(self.pause_on_global_fullscreen),
+            pause_on_idle_inhibit: other.pause_on_idle_inhibit.or(self.pause_on_idle_inhibit),
+            skip_under_tabbed: other.skip_under_tabbed.or(self.skip_under_tabbed),
+            no_single_window_override: other
+                .no_single_window_override
+                .or(self.no_single_window_override),
+            on_relayout: other.on_relayout.or(self.on_relayout),
+            notify_on_error: other.notify_on_error.or(self.notify_on_error),
+            notify_command: other.notify_command.or(self.notify_command),
+            state_file: other.state_file.or(self.state_file),
+            explain_startup: other.explain_startup.or(self.explain_startup),
+            log_format: other.log_format.or(self.log_format),
+            decisions_csv: other.decisions_csv.or(self.decisions_csv),
+            double_check: other.double_check.or(self.double_check),
+            batch_commands: other.batch_commands.or(self.batch_commands),
+            preserve_focus: other.preserve_focus.or(self.preserve_focus),
+            poll_ms: other.poll_ms.or(self.poll_ms),
+            keep_alive_ms: other.keep_alive_ms.or(self.keep_alive_ms),
+            ipc_timeout_ms: other.ipc_timeout_ms.or(self.ipc_timeout_ms),
+            warmup_events: other.warmup_events.or(self.warmup_events),
+            relayout_all_on_start: other.relayout_all_on_start.or(self.relayout_all_on_start),
+            no_act_on_own_commands: other.no_act_on_own_commands.or(self.no_act_on_own_commands),
+            no_skip_same_focus: other.no_skip_same_focus.or(self.no_skip_same_focus),
+            container_layout_cache: other.container_layout_cache.or(self.container_layout_cache),
+            per_container_ms: other.per_container_ms.or(self.per_container_ms),
+            max_workspaces_tracked: other.max_workspaces_tracked.or(self.max_workspaces_tracked),
+            new_window_grace_ms: other.new_window_grace_ms.or(self.new_window_grace_ms),
+            relayout_only_before_new: other
+                .relayout_only_before_new
+                .or(self.relayout_only_before_new),
+            relayout_on_resize: other.relayout_on_resize.or(self.relayout_on_resize),
+            stale_retry_ms: other.stale_retry_ms.or(self.stale_retry_ms),
+            deadzone_low: other.deadzone_low.or(self.deadzone_low),
+            deadzone_high: other.deadzone_high.or(self.deadzone_high),
+            split_target: other.split_target.or(self.split_target),
+            collapse_single_child: other.collapse_single_child.or(self.collapse_single_child),
+            pause_in_modes: other.pause_in_modes.or(self.pause_in_modes),
+            pause_when_locked: other.pause_when_locked.or(self.pause_when_locked),
+            lock_app: other.lock_app.or(self.lock_app),
+            manage_con_id: other.manage_con_id.or(self.manage_con_id),
+            splith_below_count: other.splith_below_count.or(self.splith_below_count),
+            splitv_above_count: other.splitv_above_count.or(self.splitv_above_count),
+            tie_break: other.tie_break.or(self.tie_break),
+            ratio_curve: other.ratio_curve.or(self.ratio_curve),
+            learn_app_layouts: other.learn_app_layouts.or(self.learn_app_layouts),
+            learn_confidence: other.learn_confidence.or(self.learn_confidence),
+            auto_ratio: other.auto_ratio.or(self.auto_ratio),
+            auto_ratio_output: other.auto_ratio_output.or(self.auto_ratio_output),
+            use_physical_size: other.use_physical_size.or(self.use_physical_size),
+            stats_on_exit: other.stats_on_exit.or(self.stats_on_exit),
+            swallow_apps: other.swallow_apps.or(self.swallow_apps),
+            swallow_delay_ms: other.swallow_delay_ms.or(self.swallow_delay_ms),
+        }
+    }
+
+    fn parse(text: &str) -> Result<FileConfig, String> {
+        let table = parse_toml(text)?;
+        serde_json::from_value(Value::Object(table)).map_err(|err| err.to_string())
+    }
+
+    /**
+     * Read a config file, a missing file is the same as an empty one
+     */
+    fn load(path: &Path) -> Result<FileConfig, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                FileConfig::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(FileConfig::default()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+}
+
+/**
+ * System config file location, packagers and tests may need it elsewhere
+ */
+fn system_config_path() -> PathBuf {
+    env::var_os("AUTOTILING_SYSTEM_CONFIG")
+        .filter(|path| !path.is_empty())
+        .map_or_else(|| PathBuf::from(SYSTEM_CONFIG), PathBuf::from)
+}
+
+/**
+ * Per-user config file location, following the XDG base directory spec
+ */
+fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("autotiling-rs").join("config.toml"))
+}
+
+impl Settings {
+    /**
+     * Combine the system config, the user config and the command line, later sources override
+     * earlier ones
+     */
+    pub fn load(cli: &Cli) -> Result<Settings, String> {
+        let mut paths = vec![system_config_path()];
+        paths.extend(user_config_path());
+        Settings::load_layered(cli, &paths)
+    }
+
+    /**
+     * Like `load`, with the config files at `paths`, later files override earlier ones
+     */
+    fn load_layered(cli: &Cli, paths: &[PathBuf]) -> Result<Settings, String> {
+        let mut file = FileConfig::default();
+        for path in paths {
+            file = file.merge(FileConfig::load(path)?);
+        }
+        Settings::resolve(cli, file)
+    }
+
+    /**
+     * Combine the command line with `file`, without reading any config file
+     */
+    pub fn resolve(cli: &Cli, file: FileConfig) -> Result<Settings, String> {
+        let deadzone = match (
+            cli.deadzone_low.or(file.deadzone_low),
+            cli.deadzone_high.or(file.deadzone_high),
+        ) {
+            (Some(low), Some(high)) if low <= high => Some((low, high)),
+            (Some(low), Some(high)) => {
+                return Err(format!(
+                    "deadzone-low ({}) must not be above deadzone-high ({})",
+                    low, high
+                ))
+            }
+            (None, None) => None,
+            _ => return Err("deadzone-low and deadzone-high must be set together".to_string()),
+        };
+
+        let workspace_modes = if cli.workspace_mode.is_empty() {
+            file.workspace_mode.unwrap_or_default()
+        } else {
+            cli.workspace_mode.clone()
+        };
+        let workspace_modes = workspace_modes
+            .iter()
+            .map(|spec| parse_workspace_mode(spec))
+            .collect::<Result<_, _>>()?;
+
+        let ratio_curve = match cli.ratio_curve.as_ref().or(file.ratio_curve.as_ref()) {
+            Some(spec) => Some(parse_ratio_curve(spec)?),
+            None => None,
+        };
+
+        let settings = Settings {
+            mode: cli.mode.or(file.mode).unwrap_or(Mode::Ratio),
+            workspace_modes,
+            ratio: cli.ratio.or(file.ratio).unwrap_or(DEFAULT_RATIO),
+            ratio_source: match (cli.ratio, file.ratio) {
+                (Some(_), _) => "command line or $AUTOTILING_RATIO",
+                (None, Some(_)) => "config file",
+                (None, None) => "default",
+            },
+            workspaces: if cli.workspace.is_empty() {
+                file.workspace.unwrap_or_default()
+            } else {
+                cli.workspace.clone()
+            },
+            output_types: if cli.output_type.is_empty() {
+                file.output_type.unwrap_or_default()
+            } else {
+                cli.output_type.clone()
+            },
+            ratio_target: cli
+                .ratio_target
+                .or(file.ratio_target)
+                .unwrap_or(RatioTarget::Focused),
+            exclude_decorations: cli
+                .exclude_decorations
+                .or(file.exclude_decorations)
+                .unwrap_or(false),
+            reverse: cli.reverse.or(file.reverse).unwrap_or(false),
+            reverse_outputs: if cli.reverse_output.is_empty() {
+                file.reverse_output.unwrap_or_default()
+            } else {
+                cli.reverse_output.clone()
+            },
+            tabbed_threshold_ratio: cli.tabbed_threshold_ratio.or(file.tabbed_threshold_ratio),
+            ultrawide_threshold: cli.ultrawide_threshold.or(file.ultrawide_threshold),
+            pause_on_global_fullscreen: cli
+                .pause_on_global_fullscreen
+                .or(file.pause_on_global_fullscreen)
+                .unwrap_or(false),
+            pause_on_idle_inhibit: cli
+                .pause_on_idle_inhibit
+                .or(file.pause_on_idle_inhibit)
+                .unwrap_or(false),
+            skip_under_tabbed: cli
+                .skip_under_tabbed
+                .or(file.skip_under_tabbed)
+                .unwrap_or(false),
+            no_single_window_override: cli
+                .no_single_window_override
+                .or(file.no_single_window_override)
+                .unwrap_or(false),
+            on_relayout: cli.on_relayout.clone().or(file.on_relayout),
+            notify_on_error: cli
+                .notify_on_error
+                .or(file.notify_on_error)
+                .unwrap_or(false),
+            notify_command: cli
+                .notify_command
+                .clone()
+                .or(file.notify_command)
+                .unwrap_or_else(|| "notify-send autotiling-rs \"$AT_ERROR\"".to_string()),
+            state_file: cli.state_file.clone().or(file.state_file),
+            explain_startup: cli
+                .explain_startup
+                .or(file.explain_startup)
+                .unwrap_or(false),
+            log_format: cli.log_format.or(file.log_format),
+            decisions_csv: cli.decisions_csv.clone().or(file.decisions_csv),
+            double_check: cli.double_check.or(file.double_check).unwrap_or(false),
+            batch_commands: cli.batch_commands.or(file.batch_commands).unwrap_or(false),
+            preserve_focus: cli.preserve_focus.or(file.preserve_focus).unwrap_or(false),
+            poll_ms: cli.poll_ms.or(file.poll_ms).unwrap_or(0),
+            keep_alive_ms: cli.keep_alive_ms.or(file.keep_alive_ms).unwrap_or(0),
+            ipc_timeout_ms: cli.ipc_timeout_ms.or(file.ipc_timeout_ms).unwrap_or(0),
+            warmup_events: cli.warmup_events.or(file.warmup_events).unwrap_or(0),
+            relayout_all_on_start: cli
+                .relayout_all_on_start
+                .or(file.relayout_all_on_start)
+                .unwrap_or(false),
+            no_act_on_own_commands: cli
+                .no_act_on_own_commands
+                .or(file.no_act_on_own_commands)
+                .unwrap_or(false),
+            no_skip_same_focus: cli
+                .no_skip_same_focus
+                .or(file.no_skip_same_focus)
+                .unwrap_or(false),
+            container_layout_cache: cli
+                .container_layout_cache
+                .or(file.container_layout_cache)
+                .unwrap_or(false),
+            per_container_ms: cli.per_container_ms.or(file.per_container_ms).unwrap_or(0),
+            max_workspaces_tracked: cli
+                .max_workspaces_tracked
+                .or(file.max_workspaces_tracked)
+                .unwrap_or(100),
+            new_window_grace_ms: cli
+                .new_window_grace_ms
+                .or(file.new_window_grace_ms)
+                .unwrap_or(0),
+            relayout_only_before_new: cli
+                .relayout_only_before_new
+                .or(file.relayout_only_before_new)
+                .unwrap_or(false),
+            relayout_on_resize: cli
+                .relayout_on_resize
+                .or(file.relayout_on_resize)
+                .unwrap_or(false),
+            stale_retry_ms: cli.stale_retry_ms.or(file.stale_retry_ms).unwrap_or(0),
+            deadzone,
+            split_target: cli
+                .split_target
+                .or(file.split_target)
+                .unwrap_or(SplitTarget::Parent),
+            collapse_single_child: cli
+                .collapse_single_child
+                .or(file.collapse_single_child)
+                .unwrap_or(false),
+            pause_in_modes: if cli.pause_in_modes.is_empty() {
+                file.pause_in_modes.unwrap_or_default()
+            } else {
+                cli.pause_in_modes.clone()
+            },
+            pause_when_locked: cli
+                .pause_when_locked
+                .or(file.pause_when_locked)
+                .unwrap_or(false),
+            lock_app: cli
+                .lock_app
+                .clone()
+                .or(file.lock_app)
+                .unwrap_or_else(|| "swaylock".to_string()),
+            manage_con_id: if cli.manage_con_id.is_empty() {
+                file.manage_con_id.unwrap_or_default()
+            } else {
+                cli.manage_con_id.clone()
+            },
+            splith_below_count: cli.splith_below_count.or(file.splith_below_count),
+            splitv_above_count: cli.splitv_above_count.or(file.splitv_above_count),
+            tie_break: cli.tie_break.or(file.tie_break).unwrap_or(TieBreak::Keep),
+            ratio_curve,
+            learn_app_layouts: cli
+                .learn_app_layouts
+                .or(file.learn_app_layouts)
+                .unwrap_or(false),
+            learn_confidence: cli
+                .learn_confidence
+                .or(file.learn_confidence)
+                .unwrap_or(0.75),
+            auto_ratio: cli.auto_ratio.or(file.auto_ratio).unwrap_or(false),
+            use_physical_size: cli
+                .use_physical_size
+                .or(file.use_physical_size)
+                .unwrap_or(false),
+            auto_ratio_outputs: if cli.auto_ratio_output.is_empty() {
+                file.auto_ratio_output.unwrap_or_default()
+            } else {
+                cli.auto_ratio_output.clone()
+            },
+            stats_on_exit: cli.stats_on_exit.or(file.stats_on_exit).unwrap_or(false),
+            swallow_apps: cli
+                .swallow_apps
+                .clone()
+                .or(file.swallow_apps)
+                .map(|pattern| Pattern::new(&pattern))
+                .transpose()?,
+            swallow_delay_ms: cli.swallow_delay_ms.or(file.swallow_delay_ms).unwrap_or(0),
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /**
+     * Whether the ratio on `output` is derived from its shape
+     */
+    pub fn auto_ratio_on(&self, output: &str) -> bool {
+        self.auto_ratio || self.auto_ratio_outputs.iter().any(|name| name == output)
+    }
+
+    /**
+     * Whether the split directions are swapped on `output`
+     */
+    pub fn reversed_on(&self, output: Option<&str>) -> bool {
+        let listed =
+            output.is_some_and(|output| self.reverse_outputs.iter().any(|name| name == output));
+        self.reverse != listed
+    }
+
+    /**
+     * The mode for a workspace, --workspace-mode matches either its number or its name
+     */
+    pub fn mode_on(&self, workspace: Option<&Node>) -> Mode {
+        let matches = |key: &str| {
+            workspace.is_some_and(|ws| {
+                ws.name.as_deref() == Some(key)
+                    || ws.num.map(|num| num.to_string()).as_deref() == Some(key)
+            })
+        };
+        self.workspace_modes
+            .iter()
+            .find(|(key, _)| matches(key))
+            .map_or(self.mode, |&(_, mode)| mode)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let positive = |name: &str, value: f32| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be a positive number, got {}", name, value))
+            }
+        };
+
+        positive("ratio", self.ratio)?;
+        if let Some(threshold) = self.tabbed_threshold_ratio {
+            positive("tabbed-threshold-ratio", threshold)?;
+        }
+        if !(self.learn_confidence > 0.5 && self.learn_confidence <= 1.0) {
+            return Err(format!(
+                "learn-confidence must be above 0.5 and at most 1, got {}",
+                self.learn_confidence
+            ));
+        }
+        if let Some(threshold) = self.ultrawide_threshold {
+            positive("ultrawide-threshold", threshold)?;
+        }
+        if let Some((low, high)) = self.deadzone {
+            positive("deadzone-low", low)?;
+            positive("deadzone-high", high)?;
+        }
+        Ok(())
+    }
+}
+
+/// The settings without any command line, environment or config file
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::resolve(&Cli::default(), FileConfig::default()).expect("invalid defaults")
+    }
+}
+
+/**
+ * Parse a `WORKSPACE=MODE` pair of --workspace-mode
+ */
+fn parse_workspace_mode(spec: &str) -> Result<(String, Mode), String> {
+    let (workspace, mode) = spec
+        .split_once('=')
+        .filter(|(workspace, _)| !workspace.is_empty())
+        .ok_or_else(|| format!("workspace-mode: expected `WORKSPACE=MODE`, got `{}`", spec))?;
+    let mode = Mode::from_str(mode, true).map_err(|err| format!("workspace-mode: {}", err))?;
+    Ok((workspace.to_string(), mode))
+}
+
+/**
+ * Parse the `RATIO:LAYOUT,...` breakpoints of --ratio-curve
+ */
+fn parse_ratio_curve(spec: &str) -> Result<Vec<(f32, Layout)>, String> {
+    let mut curve =
+        spec.split(',')
+            .map(|point| {
+                let (ratio, layout) = point.trim().split_once(':').ok_or_else(|| {
+                    format!("ratio-curve: expected `RATIO:LAYOUT`, got `{}`", point)
+                })?;
+                let ratio = ratio
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|ratio| ratio.is_finite() && *ratio >= 0.0)
+                    .ok_or_else(|| format!("ratio-curve: invalid ratio `{}`", ratio))?;
+                let layout = Layout::from_str(layout, true)
+                    .map_err(|err| format!("ratio-curve: {}", err))?;
+                Ok((ratio, layout))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+    curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if curve.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err(format!(
+            "ratio-curve: the same ratio appears twice in `{}`",
+            spec
+        ));
+    }
+    Ok(curve)
+}
+
+/**
+ * Parse the subset of TOML that makes sense for our config: top level `key = value` pairs,
+ * where a value is a string, a number, a boolean or a single line array of those.
+ */
+fn parse_toml(text: &str) -> Result<Map<String, Value>, String> {
+    let mut table = Map::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let err = |msg: &str| format!("line {}: {}", lineno + 1, msg);
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(err("tables are not supported"));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err("expected `key = value`"))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(err("missing key"));
+        }
+
+        let mut rest = value.trim();
+        let value = parse_value(&mut rest).map_err(|msg| err(&msg))?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(err("unexpected characters after the value"));
+        }
+
+        if table.insert(key.to_string(), value).is_some() {
+            return Err(err(&format!("duplicate key `{}`", key)));
+        }
+    }
+    Ok(table)
+}
+
+/**
+ * Parse a value from the start of `input`, leaving the unparsed remainder in it
+ */
+fn parse_value(input: &mut &str) -> Result<Value, String> {
+    let s = *input;
+    if let Some(inner) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = inner.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    *input = &inner[i + 1..];
+                    return Ok(Value::String(out));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    _ => return Err("invalid escape sequence".to_string()),
+                },
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    } else if let Some(inner) = s.strip_prefix('\'') {
+        let end = inner.find('\'').ok_or("unterminated string")?;
+        *input = &inner[end + 1..];
+        Ok(Value::String(inner[..end].to_string()))
+    } else if let Some(mut inner) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            inner = inner.trim_start();
+            if let Some(rest) = inner.strip_prefix(']') {
+                *input = rest;
+                return Ok(Value::Array(items));
+            }
+            items.push(parse_value(&mut inner)?);
+            inner = inner.trim_start();
+            if let Some(rest) = inner.strip_prefix(',') {
+                inner = rest;
+            } else if !inner.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    } else {
+        let end = s
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(s.len());
+        let (word, rest) = s.split_at(end);
+        *input = rest;
+        match word {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => {
+                let word = word.replace('_', "");
+                if let Ok(int) = word.parse::<i64>() {
+                    Ok(Value::Number(int.into()))
+                } else {
+                    word.parse::<f64>()
+                        .ok()
+                        .and_then(Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| format!("invalid value `{}`", word))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::cli;
+
+    fn table(text: &str) -> Result<Value, String> {
+        parse_toml(text).map(Value::Object)
+    }
+
+    #[test]
+    fn toml_values() {
+        let text = r#"
+# a comment
+string = "a \"quoted\"\tvalue\\" # trailing comment
+literal = 'C:\no\escapes'
+int = 1_000
+negative = -3
+float = 0.75
+yes = true
+no = false
+list = [ "DP", 'HDMI' ,"eDP", ]
+numbers = [1, 2.5]
+empty = []
+"#;
+        assert_eq!(
+            table(text).unwrap(),
+            json!({
+                "string": "a \"quoted\"\tvalue\\",
+                "literal": "C:\\no\\escapes",
+                "int": 1000,
+                "negative": -3,
+                "float": 0.75,
+                "yes": true,
+                "no": false,
+                "list": ["DP", "HDMI", "eDP"],
+                "numbers": [1, 2.5],
+                "empty": [],
+            })
+        );
+    }
+
+    #[test]
+    fn toml_errors() {
+        let err = |text| table(text).unwrap_err();
+        assert_eq!(err("[section]"), "line 1: tables are not supported");
+        assert_eq!(err("ratio"), "line 1: expected `key = value`");
+        assert_eq!(err(" = 1"), "line 1: missing key");
+        assert_eq!(err("a = 1\na = 2"), "line 2: duplicate key `a`");
+        assert_eq!(err("a = \"open"), "line 1: unterminated string");
+        assert_eq!(err("a = 'open"), "line 1: unterminated string");
+        assert_eq!(err("a = \"\\x\""), "line 1: invalid escape sequence");
+        assert_eq!(err("a = [1 2]"), "line 1: expected `,` or `]` in array");
+        assert_eq!(
+            err("a = \"x\" y"),
+            "line 1: unexpected characters after the value"
+        );
+        assert_eq!(err("a = yes"), "line 1: invalid value `yes`");
+    }
+
+    #[test]
+    fn config_file_keys() {
+        assert!(FileConfig::parse("ratio = 0.5\nratio-target = \"parent\"").is_ok());
+        assert!(FileConfig::parse("no-such-option = 1")
+            .unwrap_err()
+            .starts_with("unknown field `no-such-option`"));
+    }
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let dir = env::temp_dir().join(format!("autotiling-rs-config-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.toml");
+        let user = dir.join("user.toml");
+        fs::write(&system, "ratio = 0.5\nmode = \"spiral\"\npoll-ms = 100\n").unwrap();
+        fs::write(&user, "ratio = 0.6\nmode = \"alternate\"\n").unwrap();
+        let paths = [system, user, dir.join("missing.toml")];
+
+        let settings = Settings::load_layered(&cli(&[]), &paths).unwrap();
+        assert_eq!(settings.ratio, 0.6);
+        assert_eq!(settings.ratio_source, "config file");
+        assert_eq!(settings.mode, Mode::Alternate);
+        assert_eq!(settings.poll_ms, 100);
+
+        let settings = Settings::load_layered(&cli(&["--ratio", "0.7"]), &paths).unwrap();
+        assert_eq!(settings.ratio, 0.7);
+        assert_eq!(settings.ratio_source, "command line or $AUTOTILING_RATIO");
+        assert_eq!(settings.mode, Mode::Alternate);
+        assert_eq!(settings.poll_ms, 100);
+
+        let settings = Settings::load_layered(&cli(&[]), &paths[..1]).unwrap();
+        assert_eq!(settings.ratio, 0.5);
+        assert_eq!(settings.mode, Mode::Spiral);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn command_line_turns_off_what_a_config_file_turned_on() {
+        let resolve = |args: &[&str]| {
+            let file = FileConfig::parse("reverse = true\nno-skip-same-focus = true\n").unwrap();
+            Settings::resolve(&cli(args), file).unwrap()
+        };
+
+        let settings = resolve(&[]);
+        assert!(settings.reverse);
+        assert!(settings.no_skip_same_focus);
+
+        let settings = resolve(&["--reverse=false", "--no-skip-same-focus=false"]);
+        assert!(!settings.reverse);
+        assert!(!settings.no_skip_same_focus);
+
+        // a bare switch still turns it on
+        let off = FileConfig::parse("reverse = false\n").unwrap();
+        assert!(
+            Settings::resolve(&cli(&["--reverse"]), off)
+                .unwrap()
+                .reverse
+        );
+    }
+
+    #[test]
+    fn ratio_curve() {
+        assert_eq!(
+            parse_ratio_curve("2.5:tabbed, 0:splitv,0.8:SplitH"),
+            Ok(vec![
+                (0.0, Layout::Splitv),
+                (0.8, Layout::Splith),
+                (2.5, Layout::Tabbed)
+            ])
+        );
+
+        let err = |spec| parse_ratio_curve(spec).unwrap_err();
+        assert_eq!(
+            err("0.8"),
+            "ratio-curve: expected `RATIO:LAYOUT`, got `0.8`"
+        );
+        assert_eq!(err("-1:splitv"), "ratio-curve: invalid ratio `-1`");
+        assert_eq!(err("inf:splitv"), "ratio-curve: invalid ratio `inf`");
+        assert!(err("0:stacked").starts_with("ratio-curve: "));
+        assert_eq!(
+            err("0:splitv,0.0:splith"),
+            "ratio-curve: the same ratio appears twice in `0:splitv,0.0:splith`"
+        );
+    }
+}
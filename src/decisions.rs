@@ -0,0 +1,132 @@
+//! Every decision as a CSV row, see --decisions-csv
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "timestamp,workspace,output,app_id,width,height,real_ratio,effective_ratio,layout,ignored_reason";
+
+pub struct DecisionLog {
+    file: File,
+}
+
+/// One row of the log
+pub struct Row<'a> {
+    pub workspace: &'a str,
+    pub output: &'a str,
+    pub app_id: &'a str,
+    pub size: (i32, i32),
+    pub real_ratio: f32,
+    pub effective_ratio: f32,
+    /// empty for skipped decisions
+    pub layout: &'a str,
+    /// empty unless skipped
+    pub ignored_reason: &'a str,
+}
+
+impl DecisionLog {
+    /**
+     * Append to the file at `path`, a new file starts with the header
+     */
+    pub fn open(path: &Path) -> Result<DecisionLog, String> {
+        let err = |err| format!("could not open {}: {}", path.display(), err);
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(err)?;
+        if file.metadata().map_err(err)?.len() == 0 {
+            writeln!(file, "{}", HEADER).map_err(err)?;
+        }
+        Ok(DecisionLog { file })
+    }
+
+    /**
+     * Write a row made at `time`, it goes to the file right away since File does not buffer
+     */
+    pub fn write(&mut self, row: &Row, time: SystemTime) -> Result<(), String> {
+        let timestamp = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        writeln!(
+            self.file,
+            "{:.3},{},{},{},{},{},{:.3},{:.3},{},{}",
+            timestamp,
+            quote(row.workspace),
+            quote(row.output),
+            quote(row.app_id),
+            row.size.0,
+            row.size.1,
+            row.real_ratio,
+            row.effective_ratio,
+            row.layout,
+            quote(row.ignored_reason)
+        )
+        .map_err(|err| format!("could not write a decision: {}", err))
+    }
+}
+
+/**
+ * Quote a field if it would otherwise break the row
+ */
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn rows() {
+        let path = env::temp_dir().join(format!("autotiling-rs-decisions-{}.csv", process::id()));
+        let _ = fs::remove_file(&path);
+        let row = Row {
+            workspace: "1",
+            output: "eDP-1",
+            app_id: "app",
+            size: (400, 1000),
+            real_ratio: 2.5,
+            effective_ratio: 0.4,
+            layout: "splitv",
+            ignored_reason: "",
+        };
+        let time = UNIX_EPOCH + Duration::from_millis(1_500);
+
+        let mut log = DecisionLog::open(&path).unwrap();
+        log.write(&row, time).unwrap();
+        // a log that is appended to gets no second header
+        let mut log = DecisionLog::open(&path).unwrap();
+        let skipped = Row {
+            workspace: "2:web, mail",
+            app_id: "say \"hi\"",
+            layout: "",
+            ignored_reason: "ratio within the deadzone",
+            ..row
+        };
+        log.write(&skipped, time).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "timestamp,workspace,output,app_id,width,height,real_ratio,effective_ratio,layout,ignored_reason",
+                "1.500,1,eDP-1,app,400,1000,2.500,0.400,splitv,",
+                "1.500,\"2:web, mail\",eDP-1,\"say \"\"hi\"\"\",400,1000,2.500,0.400,,ratio within the deadzone"
+            ]
+        );
+    }
+}
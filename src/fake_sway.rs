@@ -0,0 +1,219 @@
+//! A stand-in for sway on the other end of a connection, for the tests and --simulate. It answers
+//! the requests the daemon makes from a tree set up beforehand and records the commands it gets.
+//! On an event connection it sends the events queued up for it.
+
+// --simulate only needs a part of it
+#![cfg_attr(not(test), allow(dead_code))]
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use swayipc::Connection;
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+const RUN_COMMAND: u32 = 0;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const GET_VERSION: u32 = 7;
+const GET_BINDING_STATE: u32 = 12;
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+#[derive(Default)]
+struct Shared {
+    /// the trees get_tree() answers with, the last one stays
+    trees: VecDeque<Value>,
+    outputs: Vec<Value>,
+    /// payloads of run_command requests
+    messages: Vec<String>,
+    /// types of all requests
+    requests: Vec<u32>,
+    /// how long to wait before answering
+    delay: Option<Duration>,
+    /// close the connection instead of answering
+    hung_up: bool,
+    /// window events to send once subscribed to, see stream()
+    events: Option<Vec<Value>>,
+}
+
+#[derive(Clone)]
+pub struct FakeSway {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl FakeSway {
+    /**
+     * Start answering on a new connection, get_tree() reports `tree`
+     */
+    pub fn start(tree: Value) -> (FakeSway, Connection) {
+        let (sway, client) = FakeSway::listen(tree);
+        (sway, Connection::from(client))
+    }
+
+    /**
+     * Like start(), but with the socket of the connection
+     */
+    pub fn listen(tree: Value) -> (FakeSway, UnixStream) {
+        let (client, server) = UnixStream::pair().expect("no socket pair");
+        let sway = FakeSway {
+            shared: Arc::default(),
+        };
+        sway.lock().trees.push_back(tree);
+        let serving = sway.clone();
+        thread::spawn(move || serving.serve(server));
+        (sway, client)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().expect("poisoned")
+    }
+
+    /**
+     * Report `tree` once the trees so far were reported
+     */
+    pub fn then(&self, tree: Value) {
+        self.lock().trees.push_back(tree);
+    }
+
+    /// Report only `tree` from now on
+    pub fn set_tree(&self, tree: Value) {
+        let mut shared = self.lock();
+        shared.trees.clear();
+        shared.trees.push_back(tree);
+    }
+
+    /// Wait this long before every answer
+    pub fn set_delay(&self, delay: Duration) {
+        self.lock().delay = Some(delay);
+    }
+
+    /// Close the connection at the next request, the way a sway that went away does
+    pub fn hang_up(&self) {
+        self.lock().hung_up = true;
+    }
+
+    /**
+     * Once subscribed to, send the window `events` and hang up, the way the event connection of
+     * a sway that went away ends
+     */
+    pub fn stream(&self, events: Vec<Value>) {
+        self.lock().events = Some(events);
+    }
+
+    pub fn set_outputs(&self, outputs: Vec<Value>) {
+        self.lock().outputs = outputs;
+    }
+
+    /// How often the tree was asked for so far
+    pub fn tree_requests(&self) -> usize {
+        self.lock()
+            .requests
+            .iter()
+            .filter(|&&kind| kind == GET_TREE)
+            .count()
+    }
+
+    /// The run_command messages so far
+    pub fn messages(&self) -> Vec<String> {
+        self.lock().messages.clone()
+    }
+
+    /// The commands so far, one for each `;` separated part of a message
+    pub fn commands(&self) -> Vec<String> {
+        self.messages()
+            .iter()
+            .flat_map(|message| message.split(';').map(|cmd| cmd.trim().to_string()))
+            .collect()
+    }
+
+    fn serve(&self, mut stream: UnixStream) {
+        let mut header = [0; 14];
+        while stream.read_exact(&mut header).is_ok() {
+            let mut word = [0; 4];
+            word.copy_from_slice(&header[6..10]);
+            let length = u32::from_le_bytes(word) as usize;
+            word.copy_from_slice(&header[10..14]);
+            let kind = u32::from_le_bytes(word);
+            let mut payload = vec![0; length];
+            if stream.read_exact(&mut payload).is_err() {
+                return;
+            }
+
+            let (reply, delay) =
+                match self.answer(kind, String::from_utf8_lossy(&payload).into_owned()) {
+                    Some(answer) => answer,
+                    None => return,
+                };
+            if let Some(delay) = delay {
+                thread::sleep(delay);
+            }
+            if stream.write_all(&message(kind, &reply)).is_err() {
+                return;
+            }
+
+            if kind == SUBSCRIBE {
+                let events = self.lock().events.take();
+                if let Some(events) = events {
+                    for event in events {
+                        if stream.write_all(&message(WINDOW_EVENT, &event)).is_err() {
+                            return;
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The reply to a request and how long to wait with it, None to hang up
+    fn answer(&self, kind: u32, payload: String) -> Option<(Value, Option<Duration>)> {
+        let mut shared = self.lock();
+        if shared.hung_up {
+            return None;
+        }
+        shared.requests.push(kind);
+        let reply = match kind {
+            RUN_COMMAND => {
+                let count = payload.split(';').count();
+                shared.messages.push(payload);
+                Value::Array(vec![json!({ "success": true }); count])
+            }
+            // the events follow the answer, see stream()
+            SUBSCRIBE => json!({ "success": true }),
+            GET_OUTPUTS => Value::Array(shared.outputs.clone()),
+            GET_TREE => {
+                if shared.trees.len() > 1 {
+                    shared.trees.pop_front().unwrap_or_default()
+                } else {
+                    shared.trees.front().cloned().unwrap_or_default()
+                }
+            }
+            GET_VERSION => json!({
+                "major": 1,
+                "minor": 9,
+                "patch": 0,
+                "human_readable": "1.9",
+                "loaded_config_file_name": "",
+            }),
+            GET_BINDING_STATE => json!({ "name": "default" }),
+            _ => json!({ "success": false }),
+        };
+        Some((reply, shared.delay))
+    }
+}
+
+/// A message of the sway IPC with `kind` in its header
+fn message(kind: u32, payload: &Value) -> Vec<u8> {
+    let payload = payload.to_string();
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&kind.to_le_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    message
+}
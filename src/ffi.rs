@@ -0,0 +1,111 @@
+//! C ABI for tools that want the exact same decision as the daemon without linking Rust,
+//! e.g. through ctypes:
+//!
+//! ```c
+//! int autotiling_decide(const char *tree_json, float ratio);
+//! ```
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic;
+
+use swayipc::{Node, NodeLayout};
+
+use crate::config::Settings;
+use crate::layout::{decide_layout, Decision, Trace};
+use crate::outputs::Outputs;
+
+pub const LAYOUT_NONE: c_int = 0;
+pub const LAYOUT_SPLITH: c_int = 1;
+pub const LAYOUT_SPLITV: c_int = 2;
+pub const LAYOUT_TABBED: c_int = 3;
+/// The tree could not be parsed or has no focused window, or the decision failed
+pub const LAYOUT_ERROR: c_int = -1;
+
+/**
+ * Decide the layout for the focused window of `tree_json`, the output of `swaymsg -t get_tree`,
+ * with the default settings and the given `ratio`. Returns one of the `LAYOUT_*` codes.
+ *
+ * # Safety
+ *
+ * `tree_json` must be null or point to a valid nul terminated string.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn autotiling_decide(tree_json: *const c_char, ratio: f32) -> c_int {
+    if tree_json.is_null() {
+        return LAYOUT_ERROR;
+    }
+    let tree_json = match CStr::from_ptr(tree_json).to_str() {
+        Ok(json) => json,
+        Err(_) => return LAYOUT_ERROR,
+    };
+    // a panic must not unwind into the caller, that is undefined behavior
+    panic::catch_unwind(|| decide(tree_json, ratio))
+        .unwrap_or_else(|_| Err("panicked".to_string()))
+        .unwrap_or(LAYOUT_ERROR)
+}
+
+fn decide(tree_json: &str, ratio: f32) -> Result<c_int, String> {
+    let tree: Node = serde_json::from_str(tree_json).map_err(|err| err.to_string())?;
+
+    // nothing of the host process, i.e. its arguments or environment, must leak in here
+    let mut settings = Settings::default();
+    if !(ratio.is_finite() && ratio > 0.0) {
+        return Err(format!("ratio must be a positive number, got {}", ratio));
+    }
+    settings.ratio = ratio;
+
+    let plan = decide_layout(&tree, &settings, &Outputs::default(), &mut Trace::default())?;
+    Ok(match plan.decision {
+        Decision::Layout(NodeLayout::SplitH) => LAYOUT_SPLITH,
+        Decision::Layout(NodeLayout::SplitV) => LAYOUT_SPLITV,
+        Decision::Layout(NodeLayout::Tabbed) => LAYOUT_TABBED,
+        Decision::Layout(_) | Decision::Skip(_) => LAYOUT_NONE,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::testing::{desktop, focus, window};
+
+    fn decide(tree: &str, ratio: f32) -> c_int {
+        let tree = CString::new(tree).unwrap();
+        // SAFETY: the pointer comes from a CString that lives until the end of the call
+        unsafe { autotiling_decide(tree.as_ptr(), ratio) }
+    }
+
+    #[test]
+    fn decides_on_a_json_tree() {
+        let tree = focus(
+            desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+            10,
+        );
+        let tree = tree.to_string();
+        assert_eq!(decide(&tree, 0.4), LAYOUT_SPLITV);
+        assert_eq!(decide(&tree, 3.0), LAYOUT_SPLITH);
+
+        let tree = focus(desktop(vec![window(10, 1920, 1080)]), 10);
+        let tree = tree.to_string().replace("\"splith\"", "\"splitv\"");
+        assert_eq!(decide(&tree, 0.4), LAYOUT_SPLITH);
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert_eq!(decide("{", 0.4), LAYOUT_ERROR);
+        assert_eq!(decide("{}", 0.4), LAYOUT_ERROR);
+        let tree = desktop(vec![window(10, 400, 1000)]).to_string();
+        // nothing is focused
+        assert_eq!(decide(&tree, 0.4), LAYOUT_ERROR);
+        let tree = focus(desktop(vec![window(10, 400, 1000)]), 10).to_string();
+        assert_eq!(decide(&tree, -1.0), LAYOUT_ERROR);
+        assert_eq!(decide(&tree, f32::NAN), LAYOUT_ERROR);
+        // SAFETY: null is explicitly allowed
+        assert_eq!(
+            unsafe { autotiling_decide(std::ptr::null(), 0.4) },
+            LAYOUT_ERROR
+        );
+    }
+}
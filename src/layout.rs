@@ -0,0 +1,1214 @@
+use std::iter;
+use std::ptr;
+
+use swayipc::{Node, NodeLayout, NodeType};
+
+use crate::cli::{Mode, RatioTarget, SplitTarget};
+use crate::config::Settings;
+use crate::outputs::Outputs;
+use crate::strategy::{strategy, DecisionContext};
+
+/// Upper bound on how far up the tree we walk, sway trees are never anywhere near this deep
+const MAX_TREE_DEPTH: usize = 256;
+
+/// Ratios closer than this to the threshold count as a tie
+pub(crate) const RATIO_EPSILON: f32 = 1e-3;
+
+/// Marks starting with this prefix ask for a specific split of the next window, e.g. `at:splitv`
+const HINT_MARK_PREFIX: &str = "at:";
+
+/// What should happen to the focused window
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Leave the layout alone for the given reason
+    Skip(&'static str),
+    /// Set the layout of the parent container
+    Layout(NodeLayout),
+}
+
+/// A decision together with the nodes it was made for
+pub struct Plan<'a> {
+    pub focused: &'a Node,
+    pub parent: &'a Node,
+    /// the node the split command is issued on, the focused node unless --split-target says otherwise
+    pub target: &'a Node,
+    /// the container whose layout the command changes, i.e. the parent of `target`
+    pub container: &'a Node,
+    /// width and height of the measured container
+    pub size: (i32, i32),
+    /// height/width of the measured container, this is what gets compared with --ratio. NaN if
+    /// the container has no size.
+    pub real_ratio: f32,
+    /// the threshold `real_ratio` was compared with
+    pub ratio: f32,
+    /// id of the node carrying a split hint mark, together with the mark
+    pub hint: Option<(i64, &'a str)>,
+    pub decision: Decision,
+}
+
+/**
+ * Steps of the reasoning behind a decision, only collected for --why
+ */
+#[derive(Debug, Default)]
+pub struct Trace {
+    steps: Option<Vec<String>>,
+}
+
+impl Trace {
+    pub fn enabled() -> Trace {
+        Trace {
+            steps: Some(Vec::new()),
+        }
+    }
+
+    /// `step` is only evaluated if the trace is enabled, so that it costs nothing otherwise
+    pub(crate) fn step(&mut self, step: impl FnOnce() -> String) {
+        if let Some(steps) = &mut self.steps {
+            steps.push(step());
+        }
+    }
+
+    pub fn steps(&self) -> &[String] {
+        self.steps.as_deref().unwrap_or_default()
+    }
+}
+
+impl Plan<'_> {
+    /**
+     * Whether the geometry looks like sway has not caught up yet: the window has no size or
+     * its ratio sits right at the threshold
+     */
+    pub fn looks_stale(&self) -> bool {
+        self.focused.rect.width <= 0
+            || self.focused.rect.height <= 0
+            || (self.real_ratio - self.ratio).abs() <= RATIO_EPSILON
+    }
+}
+
+pub fn decide_layout<'a>(
+    tree: &'a Node,
+    settings: &Settings,
+    outputs: &Outputs,
+    trace: &mut Trace,
+) -> Result<Plan<'a>, String> {
+    let focused_node = tree
+        .find_focused_as_ref(|n| n.focused)
+        .ok_or("Could not find the focused node")?;
+    plan_for(tree, focused_node, settings, outputs, trace)
+}
+
+/**
+ * Decide the layout for `focused_node` as if it was focused
+ */
+pub fn plan_for<'a>(
+    tree: &'a Node,
+    focused_node: &'a Node,
+    settings: &Settings,
+    outputs: &Outputs,
+    trace: &mut Trace,
+) -> Result<Plan<'a>, String> {
+    // the root has no parent, but is skipped as well as any other node that is not a window
+    let parent = get_parent(tree, focused_node)
+        .or_else(|| ptr::eq(focused_node, tree).then_some(tree))
+        .ok_or("No parent")?;
+
+    // with --collapse-single-child, look past split containers that only wrap a single child
+    // and split the container they sit in instead
+    let (below, parent) = if settings.collapse_single_child {
+        collapse_single_child(tree, focused_node, parent)
+    } else {
+        (focused_node, parent)
+    };
+    if !ptr::eq(below, focused_node) {
+        trace.step(|| format!("looking past single child wrapper #{}", below.id));
+    }
+
+    // bsp-parent measures the rect of the parent against 1, whatever the options say, and the
+    // plan reports what it measured
+    let bsp =
+        settings.mode_on(get_ancestor(tree, focused_node, NodeType::Workspace)) == Mode::BspParent;
+    let ratio_target = if bsp {
+        RatioTarget::Parent
+    } else {
+        settings.ratio_target
+    };
+    let measured = match ratio_target {
+        RatioTarget::Focused => focused_node,
+        RatioTarget::Parent => parent,
+    };
+    let (width, height) = if settings.exclude_decorations && !bsp {
+        content_size(measured)
+    } else {
+        (measured.rect.width, measured.rect.height)
+    };
+    // whatever dividing by a zero size gives, it is not compared with anything
+    let real_ratio = if width > 0 && height > 0 {
+        let real_ratio = (height as f32) / (width as f32);
+        trace.step(|| {
+            format!(
+                "ratio of the {:?} node #{}: {}/{} = {:.3}",
+                ratio_target, measured.id, height, width, real_ratio
+            )
+        });
+        real_ratio
+    } else {
+        trace.step(|| {
+            format!(
+                "the {:?} node #{} has no size: {}x{}",
+                ratio_target, measured.id, width, height
+            )
+        });
+        f32::NAN
+    };
+
+    // with --auto-ratio, the threshold follows the shape of the output the window is on
+    let output = get_ancestor(tree, focused_node, NodeType::Output)
+        .and_then(|output| output.name.as_deref())
+        .filter(|name| settings.auto_ratio_on(name));
+    let physical = settings.use_physical_size;
+    let ratio = match output.and_then(|name| Some((name, outputs.aspect(name, physical)?))) {
+        _ if bsp => {
+            trace.step(|| "threshold 1 of --mode bsp-parent".to_string());
+            1.0
+        }
+        Some((name, aspect)) => {
+            trace.step(|| {
+                format!(
+                    "threshold {:.3} from the {} size of output {}",
+                    aspect,
+                    if physical { "physical" } else { "logical" },
+                    name
+                )
+            });
+            aspect
+        }
+        None => {
+            trace.step(|| {
+                format!(
+                    "threshold {} from the {}",
+                    settings.ratio, settings.ratio_source
+                )
+            });
+            settings.ratio
+        }
+    };
+
+    let (target, container) = match settings.split_target {
+        SplitTarget::Parent => (below, parent),
+        SplitTarget::Outermost => get_outermost(tree, below, parent),
+    };
+
+    let hint = [focused_node, parent].iter().find_map(|node| {
+        node.marks
+            .iter()
+            .find(|mark| hint_layout(mark).is_some())
+            .map(|mark| (node.id, mark.as_str()))
+    });
+    let hint_layout = hint.and_then(|(_, mark)| hint_layout(mark));
+
+    let ctx = DecisionContext {
+        tree,
+        focused: focused_node,
+        parent,
+        workspace: get_ancestor(tree, focused_node, NodeType::Workspace),
+        output: get_ancestor(tree, focused_node, NodeType::Output),
+        size: (width, height),
+        real_ratio,
+        ratio,
+        settings,
+    };
+
+    Ok(Plan {
+        focused: focused_node,
+        parent,
+        target,
+        container,
+        size: (width, height),
+        real_ratio,
+        ratio,
+        hint,
+        decision: decide(&ctx, hint_layout, trace),
+    })
+}
+
+/**
+ * The checks that apply in every mode, then the decision of the mode's strategy
+ */
+fn decide(ctx: &DecisionContext, hint: Option<NodeLayout>, trace: &mut Trace) -> Decision {
+    let DecisionContext {
+        tree,
+        focused: focused_node,
+        parent,
+        settings,
+        ..
+    } = *ctx;
+
+    // focus can rest on an empty workspace or on nodes newer sway versions may add, only
+    // windows are ours to lay out
+    if !matches!(
+        focused_node.node_type,
+        NodeType::Con | NodeType::FloatingCon
+    ) {
+        trace.step(|| format!("focused node is a {:?}", focused_node.node_type));
+        return Decision::Skip("focused node is not a window");
+    }
+
+    if !settings.workspaces.is_empty() {
+        let num = get_ancestor(tree, focused_node, NodeType::Workspace).and_then(|ws| ws.num);
+        let matches = num.is_some_and(|num| settings.workspaces.contains(&num));
+        trace.step(|| {
+            format!(
+                "workspace {:?} in --workspace {:?}: {}",
+                num, settings.workspaces, matches
+            )
+        });
+        if !matches {
+            return Decision::Skip("workspace not in --workspace");
+        }
+    }
+
+    if !settings.output_types.is_empty() {
+        let name = get_ancestor(tree, focused_node, NodeType::Output)
+            .and_then(|output| output.name.as_deref())
+            .unwrap_or_default();
+        let kind = connector_type(name);
+        let matches = settings
+            .output_types
+            .iter()
+            .any(|wanted| wanted.eq_ignore_ascii_case(kind));
+        trace.step(|| {
+            format!(
+                "output {} of type {} in --output-type {:?}: {}",
+                name, kind, settings.output_types, matches
+            )
+        });
+        if !matches {
+            return Decision::Skip("output type not in --output-type");
+        }
+    }
+
+    if !settings.manage_con_id.is_empty() {
+        let managed = settings.manage_con_id.contains(&focused_node.id)
+            || settings.manage_con_id.contains(&parent.id);
+        trace.step(|| {
+            format!(
+                "#{} or its parent #{} in --manage-con-id {:?}: {}",
+                focused_node.id, parent.id, settings.manage_con_id, managed
+            )
+        });
+        if !managed {
+            return Decision::Skip("container not in --manage-con-id");
+        }
+    }
+
+    // check for special cases when we should not do anything
+    let ignored = should_we_ignore_this_window(focused_node);
+    trace.step(|| {
+        format!(
+            "floating, fullscreen, stacked or tabbed: {} (type {:?}, layout {:?}, percent {:?})",
+            ignored, focused_node.node_type, focused_node.layout, focused_node.percent
+        )
+    });
+    if ignored {
+        return Decision::Skip("floating, fullscreen, stacked or tabbed window");
+    }
+
+    // some users keep whole workspaces tabbed and never want splits anywhere inside them
+    if settings.skip_under_tabbed {
+        let tabbed = ancestors(tree, focused_node)
+            .find(|n| matches!(n.layout, NodeLayout::Tabbed | NodeLayout::Stacked));
+        trace.step(|| format!("tabbed or stacked ancestor: {:?}", tabbed.map(|n| n.id)));
+        if tabbed.is_some() {
+            return Decision::Skip("tabbed or stacked ancestor");
+        }
+    }
+
+    // relaying out behind a window that is fullscreen across all outputs is jarring once it exits
+    if settings.pause_on_global_fullscreen {
+        let output = get_ancestor(tree, focused_node, NodeType::Output).unwrap_or(tree);
+        let fullscreen = any_node(output, &|n| n.fullscreen_mode == Some(2));
+        trace.step(|| format!("global fullscreen window on the output: {}", fullscreen));
+        if fullscreen {
+            return Decision::Skip("global fullscreen window on the output");
+        }
+    }
+
+    // video players and games inhibit idle while in use, don't shuffle things around them
+    if settings.pause_on_idle_inhibit {
+        let inhibiting = focused_node.inhibit_idle == Some(true);
+        trace.step(|| format!("window inhibits idle: {}", inhibiting));
+        if inhibiting {
+            return Decision::Skip("window inhibits idle");
+        }
+    }
+
+    // the user asked for this direction explicitly
+    if let Some(layout) = hint {
+        trace.step(|| format!("split hint mark asks for {}", layout_name(&layout)));
+        return Decision::Layout(layout);
+    }
+
+    let mode = settings.mode_on(ctx.workspace);
+
+    // if there is a single window in the workspace, always split horizontaly (vertically on
+    // ultrawide outputs), unless the user wants to pick the layout of such workspaces themselves.
+    // bsp-parent has no such special case, the workspace is just another parent to it.
+    for current in iter::once(parent).chain(ancestors(tree, parent)) {
+        if current.nodes.len() != 1 || mode == Mode::BspParent {
+            break;
+        }
+        if current.node_type == NodeType::Workspace {
+            trace.step(|| "single window on the workspace".to_string());
+            if settings.no_single_window_override {
+                return Decision::Skip("single window on the workspace");
+            }
+            // side by side windows on an ultrawide output are still too wide, stack them instead
+            if let (Some(threshold), Some(output)) = (settings.ultrawide_threshold, ctx.output) {
+                let aspect = output.rect.width as f32 / output.rect.height as f32;
+                let ultrawide = output.rect.height > 0 && aspect > threshold;
+                trace.step(|| {
+                    format!(
+                        "output width/height {:.3} above --ultrawide-threshold {}: {}",
+                        aspect, threshold, ultrawide
+                    )
+                });
+                if ultrawide {
+                    return Decision::Layout(NodeLayout::SplitV);
+                }
+            }
+            return Decision::Layout(NodeLayout::SplitH);
+        }
+    }
+
+    trace.step(|| format!("mode {:?}", mode));
+    let decision = strategy(mode).decide(ctx, trace);
+
+    let output = ctx.output.and_then(|output| output.name.as_deref());
+    if !settings.reversed_on(output) {
+        return decision;
+    }
+    trace.step(|| "split direction reversed".to_string());
+    match decision {
+        Decision::Layout(NodeLayout::SplitH) => Decision::Layout(NodeLayout::SplitV),
+        Decision::Layout(NodeLayout::SplitV) => Decision::Layout(NodeLayout::SplitH),
+        decision => decision,
+    }
+}
+
+/**
+ * The split for a window of `width` x `height` on its own: SplitV if height/width is above
+ * `ratio`, SplitH otherwise, and the other way around with `reverse`. A ratio exactly at the
+ * threshold gives SplitH. Without a size to compare, i.e. a zero width or height or a `ratio`
+ * that is NaN, the result is SplitH whatever `reverse` says, as that is what sway starts with.
+ */
+pub fn aspect_layout(width: u32, height: u32, ratio: f32, reverse: bool) -> NodeLayout {
+    if width == 0 || height == 0 || ratio.is_nan() {
+        return NodeLayout::SplitH;
+    }
+    let tall = (height as f32) / (width as f32) > ratio;
+    if tall != reverse {
+        NodeLayout::SplitV
+    } else {
+        NodeLayout::SplitH
+    }
+}
+
+/**
+ * Width and height of a node without its title bar and borders
+ */
+fn content_size(node: &Node) -> (i32, i32) {
+    let border = node.current_border_width.max(0);
+    let width = node.rect.width - 2 * border;
+    let height = node.rect.height - node.deco_rect.height - 2 * border;
+    (width.max(0), height.max(0))
+}
+
+/**
+ * Connector type of an output, e.g. `HDMI` for `HDMI-A-1` or `eDP` for `eDP-1`
+ */
+fn connector_type(output: &str) -> &str {
+    output.split('-').next().unwrap_or_default()
+}
+
+/**
+ * Tiling windows below `node`, in the order they appear on screen
+ */
+pub fn tiling_leaves(node: &Node) -> Vec<&Node> {
+    if node.nodes.is_empty() {
+        return match node.node_type {
+            NodeType::Con => vec![node],
+            _ => Vec::new(),
+        };
+    }
+    node.nodes.iter().flat_map(tiling_leaves).collect()
+}
+
+/**
+ * Layout requested by a split hint mark, if the mark is one
+ */
+fn hint_layout(mark: &str) -> Option<NodeLayout> {
+    match mark.strip_prefix(HINT_MARK_PREFIX)? {
+        "splith" => Some(NodeLayout::SplitH),
+        "splitv" => Some(NodeLayout::SplitV),
+        _ => None,
+    }
+}
+
+/**
+ * Wayland app_id of the window, or its X11 class for XWayland windows
+ */
+pub fn app_id(node: &Node) -> &str {
+    node.app_id
+        .as_deref()
+        .or_else(|| {
+            node.window_properties
+                .as_ref()
+                .and_then(|p| p.class.as_deref())
+        })
+        .unwrap_or_default()
+}
+
+/**
+ * Reimplementation of Node::find_focused_as_ref, that takes closure instead of a function ptr
+ */
+pub fn node_find_focused_as_ref<F>(slf: &Node, predicate: F) -> Option<&Node>
+where
+    F: Fn(&Node) -> bool,
+{
+    if predicate(slf) {
+        return Some(slf);
+    }
+    if slf.focus.is_empty() {
+        return None;
+    }
+    let first = slf.focus[0];
+    for node in &slf.nodes {
+        if node.id == first {
+            return node_find_focused_as_ref(node, predicate);
+        }
+    }
+    for node in &slf.floating_nodes {
+        if node.id == first {
+            return node_find_focused_as_ref(node, predicate);
+        }
+    }
+    None
+}
+
+/**
+ * Like node_find_focused_as_ref, but searches the whole tree and not just the focused path
+ */
+fn node_find_as_ref<'a, F>(slf: &'a Node, predicate: &F) -> Option<&'a Node>
+where
+    F: Fn(&Node) -> bool,
+{
+    if predicate(slf) {
+        return Some(slf);
+    }
+    slf.nodes
+        .iter()
+        .chain(&slf.floating_nodes)
+        .find_map(|node| node_find_as_ref(node, predicate))
+}
+
+/**
+ * Find the node with the given id anywhere in the tree
+ */
+pub fn find_node(tree: &Node, id: i64) -> Option<&Node> {
+    node_find_as_ref(tree, &|n: &Node| n.id == id)
+}
+
+/**
+ * The container carrying `mark`
+ */
+pub fn find_marked<'a>(tree: &'a Node, mark: &str) -> Option<&'a Node> {
+    node_find_as_ref(tree, &|n: &Node| n.marks.iter().any(|m| m == mark))
+}
+
+pub fn get_parent<'a>(tree: &'a Node, current: &'a Node) -> Option<&'a Node> {
+    // the focused window is by far the most common case, so try the quick way first
+    node_find_focused_as_ref(tree, |n| n.nodes.iter().any(|nn| nn.id == current.id)).or_else(|| {
+        node_find_as_ref(tree, &|n: &Node| {
+            n.nodes.iter().any(|nn| nn.id == current.id)
+        })
+    })
+}
+
+/**
+ * Walk up from the focused node to the container right below the workspace. Returns that
+ * container together with its parent.
+ */
+fn get_outermost<'a>(tree: &'a Node, focused: &'a Node, parent: &'a Node) -> (&'a Node, &'a Node) {
+    let mut current = focused;
+    let mut parent = parent;
+    for grandparent in ancestors(tree, parent) {
+        if parent.node_type != NodeType::Con {
+            break;
+        }
+        current = parent;
+        parent = grandparent;
+    }
+    (current, parent)
+}
+
+/**
+ * Walk up from `current` past containers with exactly one child. Returns the outermost such
+ * container, or `current` if its parent has siblings, together with its parent.
+ */
+fn collapse_single_child<'a>(
+    tree: &'a Node,
+    current: &'a Node,
+    parent: &'a Node,
+) -> (&'a Node, &'a Node) {
+    let mut current = current;
+    let mut parent = parent;
+    for grandparent in ancestors(tree, parent) {
+        if parent.node_type != NodeType::Con || parent.nodes.len() != 1 {
+            break;
+        }
+        current = parent;
+        parent = grandparent;
+    }
+    (current, parent)
+}
+
+/**
+ * Iterate over the ancestors of `current`, closest first. The walk is bounded, so that
+ * malformed trees (e.g. with duplicate ids, where a node may look like its own parent)
+ * can not make us go around in circles.
+ */
+fn ancestors<'a>(tree: &'a Node, current: &'a Node) -> impl Iterator<Item = &'a Node> {
+    let mut current = current;
+    iter::from_fn(move || {
+        let parent = get_parent(tree, current)?;
+        if ptr::eq(parent, current) {
+            return None;
+        }
+        current = parent;
+        Some(parent)
+    })
+    .take(MAX_TREE_DEPTH)
+}
+
+/**
+ * Find the closest node of the given type on the path from `current` to the root
+ */
+pub fn get_ancestor<'a>(
+    tree: &'a Node,
+    current: &'a Node,
+    node_type: NodeType,
+) -> Option<&'a Node> {
+    iter::once(current)
+        .chain(ancestors(tree, current))
+        .find(|n| n.node_type == node_type)
+}
+
+/**
+ * Check whether any node in the subtree, including floating ones, satisfies the predicate
+ */
+fn any_node(node: &Node, predicate: &dyn Fn(&Node) -> bool) -> bool {
+    predicate(node)
+        || node.nodes.iter().any(|n| any_node(n, predicate))
+        || node.floating_nodes.iter().any(|n| any_node(n, predicate))
+}
+
+/**
+ * Determine, whether we should do anything with this window
+ */
+fn should_we_ignore_this_window(focused_node: &swayipc::Node) -> bool {
+    // get info from the focused child node
+    let is_stacked = focused_node.layout == NodeLayout::Stacked;
+    let is_tabbed = focused_node.layout == NodeLayout::Tabbed;
+    let is_floating = focused_node.node_type == NodeType::FloatingCon;
+    let is_full_screen = focused_node.percent.unwrap_or(1.0) > 1.0;
+
+    is_floating || is_full_screen || is_stacked || is_tabbed
+}
+
+pub fn layout_name(layout: &NodeLayout) -> &'static str {
+    match layout {
+        NodeLayout::SplitV => "splitv",
+        NodeLayout::SplitH => "splith",
+        NodeLayout::Tabbed => "tabbed",
+        _ => "nop",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swayipc::NodeLayout;
+
+    use super::*;
+    use serde_json::json;
+
+    use crate::testing::{
+        decision, desktop, focus, node, output, output_info, outputs, root, set, settings, split,
+        tree, window, workspace,
+    };
+
+    /// The focused #10 is square, the container #20 it is in is wide
+    fn square_in_wide() -> serde_json::Value {
+        focus(
+            desktop(vec![
+                split(
+                    20,
+                    "splith",
+                    1600,
+                    400,
+                    vec![window(10, 400, 400), window(11, 1200, 400)],
+                ),
+                window(12, 320, 1080),
+            ]),
+            10,
+        )
+    }
+
+    #[test]
+    fn ratio_target() {
+        let tree = square_in_wide();
+        assert_eq!(
+            decision(&tree, &["--ratio-target", "focused"]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        assert_eq!(
+            decision(&tree, &["--ratio-target", "parent"]),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn global_fullscreen_pauses() {
+        let tree = square_in_wide();
+        let tree = set(tree, 12, "fullscreen_mode", json!(2));
+        assert_eq!(
+            decision(&tree, &["--pause-on-global-fullscreen"]),
+            Decision::Skip("global fullscreen window on the output")
+        );
+        assert_eq!(decision(&tree, &[]), Decision::Layout(NodeLayout::SplitV));
+
+        // fullscreen on its own output only is fine
+        let tree = set(tree, 12, "fullscreen_mode", json!(1));
+        assert_eq!(
+            decision(&tree, &["--pause-on-global-fullscreen"]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+    }
+
+    #[test]
+    fn manage_con_id() {
+        let tree = square_in_wide();
+        let managed = Decision::Layout(NodeLayout::SplitV);
+        // the focused window or its parent
+        assert_eq!(decision(&tree, &["--manage-con-id", "10"]), managed);
+        assert_eq!(decision(&tree, &["--manage-con-id", "20"]), managed);
+        assert_eq!(
+            decision(&tree, &["--manage-con-id", "11", "--manage-con-id", "3"]),
+            Decision::Skip("container not in --manage-con-id")
+        );
+    }
+
+    /// xorshift, good enough to make up trees
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len() as u64) as usize]
+        }
+    }
+
+    /**
+     * A tree that breaks what sway promises: ids repeat, focus stacks name nodes that are not
+     * there, any node type appears anywhere, sizes are zero or negative
+     */
+    fn malformed_tree(rng: &mut Rng, depth: u32) -> serde_json::Value {
+        let node_type = rng.pick(&[
+            "root",
+            "output",
+            "workspace",
+            "con",
+            "floating_con",
+            "dockarea",
+        ]);
+        let layout = rng.pick(&["splith", "splitv", "stacked", "tabbed", "output", "none"]);
+        let size = |rng: &mut Rng| rng.below(2002) as i32 - 1;
+        let (width, height) = (size(rng), size(rng));
+        // few ids, so that they repeat
+        let mut value = node(rng.below(8) as i64, node_type, layout, width, height);
+        value["focused"] = json!(rng.below(4) == 0);
+        value["focus"] = json!((0..rng.below(3)).map(|_| rng.below(8)).collect::<Vec<_>>());
+        value["percent"] = json!(rng.below(3) as f64 * 0.75);
+        value["fullscreen_mode"] = json!(rng.below(3));
+        if rng.below(4) == 0 {
+            value["marks"] = json!([rng.pick(&["at:splith", "at:splitv", "at:bogus", "x"])]);
+        }
+        if depth > 0 {
+            let nodes: Vec<_> = (0..rng.below(4))
+                .map(|_| malformed_tree(rng, depth - 1))
+                .collect();
+            value["nodes"] = json!(nodes);
+            if rng.below(4) == 0 {
+                value["floating_nodes"] = json!([malformed_tree(rng, depth - 1)]);
+            }
+        }
+        value
+    }
+
+    fn all_nodes<'a>(node: &'a Node, nodes: &mut Vec<&'a Node>) {
+        nodes.push(node);
+        for child in node.nodes.iter().chain(&node.floating_nodes) {
+            all_nodes(child, nodes);
+        }
+    }
+
+    #[test]
+    fn malformed_trees_do_not_break_the_walks() {
+        let settings = [
+            settings(&[]),
+            settings(&[
+                "--split-target",
+                "outermost",
+                "--collapse-single-child",
+                "--skip-under-tabbed",
+                "--ratio-target",
+                "parent",
+            ]),
+            settings(&["--mode", "alternate", "--exclude-decorations"]),
+            settings(&["--mode", "spiral", "--pause-on-global-fullscreen"]),
+            settings(&["--mode", "bsp-parent", "--ultrawide-threshold", "2"]),
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100 {
+            let tree = tree(malformed_tree(&mut rng, 4));
+            node_find_focused_as_ref(&tree, |n| n.focused);
+            let mut nodes = Vec::new();
+            all_nodes(&tree, &mut nodes);
+            for node in nodes {
+                get_parent(&tree, node);
+                get_ancestor(&tree, node, NodeType::Workspace);
+                for settings in &settings {
+                    // errors are fine, panics and endless loops are not
+                    let _ = plan_for(
+                        &tree,
+                        node,
+                        settings,
+                        &Outputs::default(),
+                        &mut Trace::enabled(),
+                    );
+                }
+            }
+            for settings in &settings {
+                let _ = decide_layout(&tree, settings, &Outputs::default(), &mut Trace::default());
+            }
+        }
+    }
+
+    /// Two windows on the landscape eDP-1 and two on the portrait DP-1, `focused` has focus
+    fn two_outputs(focused: i64) -> serde_json::Value {
+        let windows = |first| vec![window(first, 500, 500), window(first + 1, 500, 500)];
+        let tree = root(vec![
+            output(
+                2,
+                "eDP-1",
+                1920,
+                1080,
+                vec![workspace(3, 1, "splith", windows(10))],
+            ),
+            output(
+                4,
+                "DP-1",
+                1080,
+                1920,
+                vec![workspace(5, 2, "splith", windows(20))],
+            ),
+        ]);
+        focus(tree, focused)
+    }
+
+    #[test]
+    fn auto_ratio_output() {
+        let outputs = outputs(vec![
+            output_info("eDP-1", 1920, 1080, 1.0),
+            output_info("DP-1", 1080, 1920, 1.0),
+        ]);
+        let settings = settings(&["--ratio", "0.6", "--auto-ratio-output", "DP-1"]);
+        let plan = |tree: &Node| {
+            let plan = decide_layout(tree, &settings, &outputs, &mut Trace::default()).unwrap();
+            (plan.ratio, plan.decision)
+        };
+
+        // the square window is wide compared to the portrait output
+        let tree = tree(two_outputs(20));
+        assert_eq!(
+            plan(&tree),
+            (1920.0 / 1080.0, Decision::Layout(NodeLayout::SplitH))
+        );
+        // but tall compared to --ratio
+        let tree = self::tree(two_outputs(10));
+        assert_eq!(plan(&tree), (0.6, Decision::Layout(NodeLayout::SplitV)));
+    }
+
+    #[test]
+    fn trace_names_the_source_of_the_threshold() {
+        let tree = tree(square_in_wide());
+        let steps = |args: &[&str], outputs: &Outputs| {
+            let mut trace = Trace::enabled();
+            decide_layout(&tree, &settings(args), outputs, &mut trace).unwrap();
+            trace.steps().to_vec()
+        };
+        let none = Outputs::default();
+
+        let default = steps(&[], &none);
+        assert!(
+            default.contains(&"threshold 0.4 from the default".to_string()),
+            "{:?}",
+            default
+        );
+
+        let given = steps(&["--ratio", "0.5"], &none);
+        assert!(
+            given.contains(&"threshold 0.5 from the command line or $AUTOTILING_RATIO".to_string()),
+            "{:?}",
+            given
+        );
+
+        let outputs = outputs(vec![output_info("eDP-1", 1920, 1080, 2.0)]);
+        let auto = steps(&["--auto-ratio"], &outputs);
+        assert!(
+            auto.contains(&"threshold 0.562 from the logical size of output eDP-1".to_string()),
+            "{:?}",
+            auto
+        );
+
+        // and the last step is what the decision came down to
+        assert_eq!(
+            default.last().unwrap(),
+            "ratio 1.000 above the threshold 0.4: true"
+        );
+    }
+
+    #[test]
+    fn workspace_mode() {
+        let windows = |first| vec![window(first, 960, 300), window(first + 1, 960, 300)];
+        let media = set(
+            workspace(4, 3, "splith", windows(20)),
+            4,
+            "name",
+            json!("3:media"),
+        );
+        let tree = root(vec![output(
+            2,
+            "eDP-1",
+            1920,
+            1080,
+            vec![workspace(3, 1, "splith", windows(10)), media],
+        )]);
+        let on = |id| focus(tree.clone(), id);
+
+        // by number and by name, the second window goes below the first
+        for args in [
+            ["--workspace-mode", "3=master-stack"],
+            ["--workspace-mode", "3:media=master-stack"],
+        ] {
+            assert_eq!(
+                decision(&on(21), &args),
+                Decision::Layout(NodeLayout::SplitV)
+            );
+            assert_eq!(
+                decision(&on(11), &args),
+                Decision::Layout(NodeLayout::SplitH)
+            );
+        }
+        assert_eq!(decision(&on(21), &[]), Decision::Layout(NodeLayout::SplitH));
+    }
+
+    #[test]
+    fn collapse_single_child() {
+        // #20 and #21 only wrap the focused #10
+        let wrapped = split(21, "splith", 960, 1080, vec![window(10, 960, 1080)]);
+        let wrapper = split(20, "splitv", 960, 1080, vec![wrapped]);
+        let tree = tree(focus(desktop(vec![wrapper, window(11, 960, 1080)]), 10));
+        let ids = |args: &[&str]| {
+            let plan = decide_layout(
+                &tree,
+                &settings(args),
+                &Outputs::default(),
+                &mut Trace::default(),
+            )
+            .unwrap();
+            (plan.parent.id, plan.target.id, plan.container.id)
+        };
+
+        assert_eq!(ids(&[]), (21, 10, 21));
+        assert_eq!(ids(&["--collapse-single-child"]), (3, 20, 3));
+    }
+
+    #[test]
+    fn output_type() {
+        let skipped = Decision::Skip("output type not in --output-type");
+        let laptop = two_outputs(10);
+        let external = two_outputs(20);
+
+        assert_eq!(
+            decision(&laptop, &["--output-type", "eDP"]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        // DP-1 is not an eDP output, nor is eDP-1 a DP one
+        assert_eq!(decision(&external, &["--output-type", "eDP"]), skipped);
+        assert_eq!(decision(&laptop, &["--output-type", "DP,HDMI"]), skipped);
+        assert_eq!(
+            decision(&external, &["--output-type", "hdmi,dp"]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+    }
+
+    #[test]
+    fn skip_under_tabbed() {
+        // workspace > #20 > #21 > #22 > focused #10, three levels below #20
+        let nested = |layout| {
+            let inner = split(
+                22,
+                "splith",
+                800,
+                1000,
+                vec![window(10, 400, 1000), window(11, 400, 1000)],
+            );
+            let middle = split(21, "splitv", 800, 1040, vec![inner, window(12, 800, 40)]);
+            let tree = desktop(vec![split(20, layout, 1920, 1080, vec![middle])]);
+            focus(tree, 10)
+        };
+        let args = ["--skip-under-tabbed"];
+        let skipped = Decision::Skip("tabbed or stacked ancestor");
+
+        assert_eq!(decision(&nested("tabbed"), &args), skipped);
+        assert_eq!(decision(&nested("stacked"), &args), skipped);
+        assert_eq!(
+            decision(&nested("splith"), &args),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        assert_eq!(
+            decision(&nested("tabbed"), &[]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+    }
+
+    #[test]
+    fn reverse_output() {
+        // the square windows are tall compared to the default ratio on either output
+        let split_v = Decision::Layout(NodeLayout::SplitV);
+        let split_h = Decision::Layout(NodeLayout::SplitH);
+        let args = ["--reverse-output", "DP-1"];
+        assert_eq!(decision(&two_outputs(20), &args), split_h);
+        assert_eq!(decision(&two_outputs(10), &args), split_v);
+
+        // together with --reverse, the listed output is the one left alone
+        let args = ["--reverse", "--reverse-output", "DP-1"];
+        assert_eq!(decision(&two_outputs(20), &args), split_v);
+        assert_eq!(decision(&two_outputs(10), &args), split_h);
+    }
+
+    #[test]
+    fn only_windows_are_laid_out() {
+        let skipped = Decision::Skip("focused node is not a window");
+        let tree = desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]);
+        for id in [1, 2, 3] {
+            assert_eq!(decision(&focus(tree.clone(), id), &[]), skipped, "#{}", id);
+        }
+
+        // i3 keeps its bars in dockarea nodes next to the workspaces
+        let mut tree = tree;
+        tree["nodes"][0]["nodes"]
+            .as_array_mut()
+            .unwrap()
+            .push(node(4, "dockarea", "dockarea", 1920, 30));
+        assert_eq!(decision(&focus(tree, 4), &[]), skipped);
+    }
+
+    #[test]
+    fn exclude_decorations() {
+        // 220/500 is above the default ratio, the 186/496 of the content is not
+        let tree = focus(
+            desktop(vec![window(10, 500, 220), window(11, 1420, 220)]),
+            10,
+        );
+        let tree = set(
+            tree,
+            10,
+            "deco_rect",
+            json!({ "x": 0, "y": 0, "width": 500, "height": 30 }),
+        );
+        let tree = set(tree, 10, "current_border_width", json!(2));
+
+        assert_eq!(decision(&tree, &[]), Decision::Layout(NodeLayout::SplitV));
+        assert_eq!(
+            decision(&tree, &["--exclude-decorations"]),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn ultrawide_threshold() {
+        let single = |width| {
+            let workspace = set(
+                workspace(3, 1, "splith", vec![window(10, width, 1440)]),
+                3,
+                "rect",
+                json!({ "x": 0, "y": 0, "width": width, "height": 1440 }),
+            );
+            focus(
+                root(vec![output(2, "DP-1", width, 1440, vec![workspace])]),
+                10,
+            )
+        };
+        let args = ["--ultrawide-threshold", "2"];
+
+        // 3440/1440 = 2.389
+        assert_eq!(
+            decision(&single(3440), &args),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        assert_eq!(
+            decision(&single(2560), &args),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+        assert_eq!(
+            decision(&single(3440), &[]),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn pause_on_idle_inhibit() {
+        let tree = square_in_wide();
+        let args = ["--pause-on-idle-inhibit"];
+        let laid_out = Decision::Layout(NodeLayout::SplitV);
+        assert_eq!(decision(&tree, &args), laid_out);
+
+        let inhibiting = set(tree, 10, "inhibit_idle", json!(true));
+        assert_eq!(
+            decision(&inhibiting, &args),
+            Decision::Skip("window inhibits idle")
+        );
+        assert_eq!(decision(&inhibiting, &[]), laid_out);
+        // another window inhibiting idle does not matter
+        let other = set(square_in_wide(), 11, "inhibit_idle", json!(true));
+        assert_eq!(decision(&other, &args), laid_out);
+    }
+
+    #[test]
+    fn bsp_parent_against_the_default() {
+        let bsp = ["--mode", "bsp-parent"];
+        // the square window is tall against --ratio, its container is wide
+        let tree = square_in_wide();
+        assert_eq!(decision(&tree, &[]), Decision::Layout(NodeLayout::SplitV));
+        assert_eq!(decision(&tree, &bsp), Decision::Layout(NodeLayout::SplitH));
+
+        // a single window on a portrait workspace
+        let portrait = json!({ "x": 0, "y": 0, "width": 1080, "height": 1920 });
+        let single = set(
+            focus(desktop(vec![window(10, 1080, 1920)]), 10),
+            3,
+            "rect",
+            portrait,
+        );
+        assert_eq!(decision(&single, &[]), Decision::Layout(NodeLayout::SplitH));
+        assert_eq!(
+            decision(&single, &bsp),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+    }
+
+    #[test]
+    fn bsp_parent_plan_reports_the_parent_ratio() {
+        let tree = tree(square_in_wide());
+        let args = ["--mode", "bsp-parent", "--ratio", "0.6"];
+        let plan = decide_layout(
+            &tree,
+            &settings(&args),
+            &Outputs::default(),
+            &mut Trace::default(),
+        )
+        .unwrap();
+
+        assert_eq!(plan.size, (1600, 400));
+        assert_eq!(plan.real_ratio, 0.25);
+        assert_eq!(plan.ratio, 1.0);
+        assert_eq!(plan.decision, Decision::Layout(NodeLayout::SplitH));
+    }
+
+    #[test]
+    fn aspect_layout_edges() {
+        use NodeLayout::{SplitH, SplitV};
+
+        for (width, height, ratio, layout) in [
+            // square against the default threshold and against 1
+            (500, 500, 0.4, SplitV),
+            (500, 500, 1.0, SplitH),
+            (500, 501, 1.0, SplitV),
+            // no size at all
+            (0, 500, 0.4, SplitH),
+            (500, 0, 0.4, SplitH),
+            (0, 0, 0.4, SplitH),
+            // extreme shapes and thresholds
+            (1, u32::MAX, 0.4, SplitV),
+            (u32::MAX, 1, 0.4, SplitH),
+            (500, 500, 0.0, SplitV),
+            (500, 500, -1.0, SplitV),
+            (500, 500, f32::INFINITY, SplitH),
+            (500, 500, f32::NAN, SplitH),
+        ] {
+            let reversed = match layout {
+                _ if width == 0 || height == 0 || ratio.is_nan() => SplitH,
+                SplitH => SplitV,
+                _ => SplitH,
+            };
+            assert_eq!(
+                aspect_layout(width, height, ratio, false),
+                layout,
+                "{}x{} against {}",
+                width,
+                height,
+                ratio
+            );
+            assert_eq!(
+                aspect_layout(width, height, ratio, true),
+                reversed,
+                "{}x{} against {}, reversed",
+                width,
+                height,
+                ratio
+            );
+        }
+    }
+
+    #[test]
+    fn no_size_records_no_ratio() {
+        let tree = tree(focus(
+            desktop(vec![window(10, 0, 1080), window(11, 960, 1080)]),
+            10,
+        ));
+        let mut trace = Trace::enabled();
+        let args = ["--ratio-curve", "0.3:splitv,0.8:tabbed"];
+        let plan = decide_layout(&tree, &settings(&args), &Outputs::default(), &mut trace).unwrap();
+
+        assert!(plan.real_ratio.is_nan());
+        assert_eq!(plan.decision, Decision::Layout(NodeLayout::SplitH));
+        let steps = trace.steps();
+        assert!(
+            steps.contains(&"the Focused node #10 has no size: 0x1080".to_string()),
+            "{:?}",
+            steps
+        );
+        assert!(
+            !steps.iter().any(|step| step.contains("ratio ")),
+            "{:?}",
+            steps
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Which way every app usually gets split, learned during the session with --learn-app-layouts.
+//! Decisions close to the threshold are then nudged towards what the app usually gets.
+
+use std::collections::HashMap;
+
+use swayipc::NodeLayout;
+
+use autotiling_rs::layout::Decision;
+
+/// Apps we keep counts for, the one with the fewest decisions makes room for a new one
+const MAX_APPS: usize = 64;
+/// How close to the threshold a ratio has to be for the decision to be nudged
+const BORDERLINE: f32 = 0.1;
+/// Decisions needed before an app has a preference at all
+const MIN_SAMPLES: u32 = 3;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    splith: u32,
+    splitv: u32,
+}
+
+impl Counts {
+    fn total(&self) -> u32 {
+        self.splith.saturating_add(self.splitv)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Learned {
+    apps: HashMap<String, Counts>,
+}
+
+impl Learned {
+    /**
+     * Learn from a clear decision if `record` says it changes the layout, or nudge a borderline
+     * one towards the app's preference. Borderline decisions are not learned from, or they would
+     * only reinforce themselves, and neither are the ones that find the layout as it is, e.g.
+     * every --poll-ms.
+     */
+    pub fn apply(
+        &mut self,
+        app_id: &str,
+        real_ratio: f32,
+        ratio: f32,
+        confidence: f32,
+        record: bool,
+        decision: &mut Decision,
+    ) {
+        if !matches!(
+            decision,
+            Decision::Layout(NodeLayout::SplitH | NodeLayout::SplitV)
+        ) {
+            return;
+        }
+
+        if (real_ratio - ratio).abs() > BORDERLINE {
+            if let (Decision::Layout(layout), true) = (&decision, record) {
+                self.record(app_id, layout);
+            }
+        } else if let Some(layout) = self.preference(app_id, confidence) {
+            *decision = Decision::Layout(layout);
+        }
+    }
+
+    fn record(&mut self, app_id: &str, layout: &NodeLayout) {
+        if !self.apps.contains_key(app_id) && self.apps.len() >= MAX_APPS {
+            let least = self
+                .apps
+                .iter()
+                .min_by_key(|(_, counts)| counts.total())
+                .map(|(app, _)| app.clone());
+            if let Some(app) = least {
+                self.apps.remove(&app);
+            }
+        }
+
+        let counts = self.apps.entry(app_id.to_string()).or_default();
+        let count = match layout {
+            NodeLayout::SplitH => &mut counts.splith,
+            _ => &mut counts.splitv,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    /**
+     * The layout the app got in at least `confidence` of its decisions
+     */
+    fn preference(&self, app_id: &str, confidence: f32) -> Option<NodeLayout> {
+        let counts = self.apps.get(app_id)?;
+        let total = counts.total();
+        if total < MIN_SAMPLES {
+            return None;
+        }
+        if counts.splith as f32 / total as f32 >= confidence {
+            Some(NodeLayout::SplitH)
+        } else if counts.splitv as f32 / total as f32 >= confidence {
+            Some(NodeLayout::SplitV)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIDENCE: f32 = 0.7;
+
+    /// Decide for a window of `app` with `real_ratio` against the threshold 1
+    fn apply(learned: &mut Learned, app: &str, real_ratio: f32, record: bool) -> Decision {
+        let layout = if real_ratio > 1.0 {
+            NodeLayout::SplitV
+        } else {
+            NodeLayout::SplitH
+        };
+        let mut decision = Decision::Layout(layout);
+        learned.apply(app, real_ratio, 1.0, CONFIDENCE, record, &mut decision);
+        decision
+    }
+
+    #[test]
+    fn borderline_decisions_follow_the_app() {
+        let mut learned = Learned::default();
+        let split_h = Decision::Layout(NodeLayout::SplitH);
+        let split_v = Decision::Layout(NodeLayout::SplitV);
+
+        for _ in 0..MIN_SAMPLES - 1 {
+            assert_eq!(apply(&mut learned, "files", 1.5, true), split_v);
+        }
+        // not enough decisions yet
+        assert_eq!(apply(&mut learned, "files", 0.95, true), split_h);
+        assert_eq!(apply(&mut learned, "files", 1.5, true), split_v);
+        assert_eq!(apply(&mut learned, "files", 0.95, true), split_v);
+        // other apps and clear decisions are left alone
+        assert_eq!(apply(&mut learned, "browser", 0.95, true), split_h);
+        assert_eq!(apply(&mut learned, "files", 0.5, true), split_h);
+    }
+
+    #[test]
+    fn divided_apps_have_no_preference() {
+        let mut learned = Learned::default();
+        for real_ratio in [1.5, 0.5, 1.5, 0.5] {
+            apply(&mut learned, "editor", real_ratio, true);
+        }
+        assert_eq!(learned.preference("editor", CONFIDENCE), None);
+        assert_eq!(learned.preference("editor", 0.5), Some(NodeLayout::SplitH));
+    }
+
+    #[test]
+    fn decisions_that_change_nothing_are_not_learned_from() {
+        let mut learned = Learned::default();
+        for _ in 0..10 {
+            apply(&mut learned, "files", 1.5, false);
+        }
+        assert_eq!(learned.preference("files", CONFIDENCE), None);
+        assert!(learned.apps.is_empty());
+    }
+
+    #[test]
+    fn memory_is_bounded() {
+        let mut learned = Learned::default();
+        for _ in 0..MIN_SAMPLES {
+            apply(&mut learned, "files", 1.5, true);
+        }
+        for app in 0..MAX_APPS * 2 {
+            apply(&mut learned, &app.to_string(), 1.5, true);
+        }
+        assert_eq!(learned.apps.len(), MAX_APPS);
+        // the app seen the most is kept
+        assert_eq!(
+            learned.preference("files", CONFIDENCE),
+            Some(NodeLayout::SplitV)
+        );
+    }
+}
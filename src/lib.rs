@@ -0,0 +1,19 @@
+//! The layout decision of autotiling-rs. The daemon in main.rs drives it from sway's events,
+//! with the `ffi` feature it is also exposed as a C ABI.
+
+pub mod cli;
+pub mod clock;
+pub mod completions;
+pub mod config;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod layout;
+pub mod outputs;
+mod pattern;
+pub mod strategy;
+#[cfg(test)]
+mod testing;
+
+// lets the test fixtures, which the daemon's tests use as well, name this crate
+#[cfg(test)]
+extern crate self as autotiling_rs;
@@ -0,0 +1,31 @@
+//! Whether the screen is locked. Lock screens are not part of the tree sway reports, so we look
+//! for the locker process instead. That takes the /proc of Linux, elsewhere the screen never
+//! counts as locked.
+
+use std::fs;
+
+/// Linux keeps only this many bytes of a process name in /proc/<pid>/comm
+const COMM_LEN: usize = 15;
+
+/**
+ * Whether a process named `name` is running
+ */
+pub fn is_running(name: &str) -> bool {
+    let name = &name.as_bytes()[..name.len().min(COMM_LEN)];
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            let pid = entry.file_name();
+            pid.to_str()
+                .is_some_and(|pid| pid.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .any(|entry| {
+            // the process may be gone already
+            fs::read(entry.path().join("comm"))
+                .is_ok_and(|comm| comm.strip_suffix(b"\n").unwrap_or(&comm) == name)
+        })
+}
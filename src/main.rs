@@ -1,139 +1,1661 @@
-use swayipc::{Connection, EventType, Node};
-use swayipc::{Event, NodeLayout, NodeType, WindowChange};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use swayipc::{Connection, EventStream, EventType, Node};
+use swayipc::{Event, NodeLayout, NodeType, WindowChange, WorkspaceChange};
 
-fn switch_splitting(conn: &mut Connection, ratio: f32) -> Result<(), String> {
+use clap::{CommandFactory, Parser};
+
+use autotiling_rs::cli::{Cli, LogFormat, Mode};
+use autotiling_rs::clock::{Clock, SystemClock};
+use autotiling_rs::completions;
+use autotiling_rs::config::Settings;
+use autotiling_rs::layout::{app_id, decide_layout, find_marked, find_node, get_ancestor};
+use autotiling_rs::layout::{
+    get_parent, layout_name, plan_for, tiling_leaves, Decision, Plan, Trace,
+};
+use autotiling_rs::outputs::Outputs;
+
+mod decisions;
+mod fake_sway;
+mod learn;
+mod lock;
+mod persist;
+mod shell;
+mod signal;
+mod simulate;
+mod stats;
+#[cfg(test)]
+#[path = "testing.rs"]
+mod testing;
+#[cfg(test)]
+mod tests;
+
+use decisions::{DecisionLog, Row};
+use learn::Learned;
+use persist::STATE_MARK_PREFIX;
+use shell::{Shell, SystemShell};
+use stats::Stats;
+
+/**
+ * Relayout the focused window. `deferred` is set when this is the postponed second look
+ * at a window.
+ */
+fn switch_splitting(
+    conn: &mut Connection,
+    settings: &Settings,
+    state: &mut State,
+    deferred: bool,
+) -> Result<(), String> {
     // get info from focused node and parent node which unfortunately requires us to call get_tree
-    let tree = conn.get_tree().map_err(|_| "get_tree() failed")?;
-    let focused_node = tree
+    let mut tree = get_tree(conn)?;
+    // even get_tree() can be behind right after a window operation, if the result looks like
+    // it, ask once more
+    if settings.stale_retry_ms > 0
+        && decide_layout(&tree, settings, &state.outputs, &mut Trace::default())?.looks_stale()
+    {
+        state
+            .clock
+            .sleep(Duration::from_millis(settings.stale_retry_ms));
+        tree = get_tree(conn)?;
+    }
+    let plan = decide_layout(&tree, settings, &state.outputs, &mut Trace::default())?;
+
+    // with terminal swallowing, the swallowed terminal and the new window swap places, give
+    // that some time to settle before looking at the layout
+    let swallowing = settings
+        .swallow_apps
+        .as_ref()
+        .is_some_and(|pattern| pattern.is_match(app_id(plan.focused)));
+    if swallowing && !deferred {
+        if settings.swallow_delay_ms == 0 {
+            state.skip(settings, "swallowing app", || {
+                describe_window(&tree, plan.focused)
+            });
+        } else {
+            state.deferred =
+                Some(state.clock.now() + Duration::from_millis(settings.swallow_delay_ms));
+        }
+        return Ok(());
+    }
+
+    let mut pending = Pending::new(plan.focused.id);
+    // the split direction only matters for the next window, so the commands wait for it to
+    // appear next to the focused one
+    if settings.relayout_only_before_new {
+        pending.explicit = true;
+        apply_plan(settings, state, &tree, plan, &mut pending);
+        state.before_new = Some(pending);
+        return Ok(());
+    }
+    apply_plan(settings, state, &tree, plan, &mut pending);
+    flush(conn, settings, state, pending)
+}
+
+/**
+ * Relayout the window with `id` whether it still has focus or not, e.g. the new window once
+ * its --new-window-grace-ms are over
+ */
+fn relayout_window(
+    conn: &mut Connection,
+    settings: &Settings,
+    state: &mut State,
+    id: i64,
+) -> Result<(), String> {
+    let tree = get_tree(conn)?;
+    // it may have been closed in the meantime
+    let window = match find_node(&tree, id) {
+        Some(window) => window,
+        None => return Ok(()),
+    };
+    let focused = tree.find_focused_as_ref(|n| n.focused).map_or(id, |n| n.id);
+    let plan = plan_for(
+        &tree,
+        window,
+        settings,
+        &state.outputs,
+        &mut Trace::default(),
+    )?;
+    let mut pending = Pending::new(focused);
+    apply_plan(settings, state, &tree, plan, &mut pending);
+    flush(conn, settings, state, pending)
+}
+
+/**
+ * Relayout every tiling window on the focused workspace, or on all workspaces, as if it was
+ * focused. SIGUSR2 asks for the former, --relayout-all-on-start for the latter.
+ */
+fn relayout_workspaces(
+    conn: &mut Connection,
+    settings: &Settings,
+    state: &mut State,
+    everywhere: bool,
+) -> Result<(), String> {
+    let tree = get_tree(conn)?;
+    let focused = tree
         .find_focused_as_ref(|n| n.focused)
         .ok_or("Could not find the focused node")?;
-    let parent = get_parent(&tree, focused_node).ok_or("No parent")?;
+    let workspaces: Vec<&Node> = if everywhere {
+        tree.nodes
+            .iter()
+            // the scratchpad lives in a workspace on the internal __i3 output
+            .filter(|output| output.name.as_deref() != Some("__i3"))
+            .flat_map(|output| &output.nodes)
+            .filter(|ws| ws.node_type == NodeType::Workspace)
+            .collect()
+    } else {
+        vec![get_ancestor(&tree, focused, NodeType::Workspace)
+            .ok_or("the focused node is not on a workspace")?]
+    };
+
+    // a whole session is too much to send command by command
+    let batched = everywhere || settings.batch_commands;
+    let mut pending = Pending::new(focused.id);
+    for window in workspaces.into_iter().flat_map(tiling_leaves) {
+        let plan = plan_for(
+            &tree,
+            window,
+            settings,
+            &state.outputs,
+            &mut Trace::default(),
+        )?;
+        apply_plan(settings, state, &tree, plan, &mut pending);
+        if !batched {
+            let batch = mem::replace(&mut pending, Pending::new(focused.id));
+            flush(conn, settings, state, batch)?;
+        }
+    }
+    flush(conn, settings, state, pending)
+}
+
+/**
+ * Commands decided on, but not yet sent to sway
+ */
+struct Pending {
+    commands: Vec<String>,
+    relayouts: Vec<Relayout>,
+    /// the window that had focus when the commands were decided on
+    focus: i64,
+    /// name the container in every command, the focus may have moved on when they are sent
+    explicit: bool,
+}
+
+impl Pending {
+    fn new(focus: i64) -> Pending {
+        Pending {
+            commands: Vec::new(),
+            relayouts: Vec::new(),
+            focus,
+            explicit: false,
+        }
+    }
+}
+
+/// What to do once a layout command went through
+struct Relayout {
+    /// index into `Pending::commands`
+    command: usize,
+    focused: i64,
+    target: i64,
+    layout: &'static str,
+    workspace: String,
+    app_id: String,
+}
+
+fn apply_plan(
+    settings: &Settings,
+    state: &mut State,
+    tree: &Node,
+    mut plan: Plan,
+    pending: &mut Pending,
+) {
+    if let Some(warning) = TREE_REPORTED.with(|reported| unhandled_tree_warning(&plan, reported)) {
+        eprintln!("warn: {}", warning);
+    }
+    let workspace = get_ancestor(tree, plan.focused, NodeType::Workspace);
+    if settings.learn_app_layouts
+        && plan.hint.is_none()
+        && settings.mode_on(workspace) == Mode::Ratio
+    {
+        let changes =
+            matches!(&plan.decision, Decision::Layout(layout) if *layout != plan.container.layout);
+        state.learned.apply(
+            app_id(plan.focused),
+            plan.real_ratio,
+            plan.ratio,
+            settings.learn_confidence,
+            changes,
+            &mut plan.decision,
+        );
+    }
 
-    // check for special cases when we should not do anything
-    if should_we_ignore_this_window(focused_node) {
+    // the tree may not show our last command for the container yet
+    let cached = match &plan.decision {
+        Decision::Layout(layout) => {
+            *layout != plan.container.layout
+                && state.layout_cached(settings, plan.target.id, layout)
+        }
+        Decision::Skip(_) => false,
+    };
+    if cached {
+        plan.decision = Decision::Skip("layout set by us recently");
+    }
+
+    if let Some(format) = settings.log_format {
+        println!("{}", decision_line(format, &plan));
+    }
+    if let Some(log) = &mut state.decisions {
+        if let Err(err) = log.write(&decision_row(tree, &plan), state.clock.system_time()) {
+            eprintln!("err: {}", err);
+        }
+    }
+
+    let new_layout = match plan.decision {
+        Decision::Layout(layout) => layout,
+        Decision::Skip(reason) => {
+            // logged with the ratios above already
+            state.stats.skip(reason);
+            state.explain(settings, reason, || {
+                let window = describe_window(tree, plan.focused);
+                if settings.workspaces.is_empty() {
+                    window
+                } else {
+                    format!("{}, --workspace {:?}", window, settings.workspaces)
+                }
+            });
+            return;
+        }
+    };
+
+    let target = (pending.explicit || !plan.target.focused).then_some(plan.target.id);
+    if let Some(cmd) = layout_command(&new_layout, plan.container, target) {
+        pending.relayouts.push(Relayout {
+            command: pending.commands.len(),
+            focused: plan.focused.id,
+            target: plan.target.id,
+            layout: layout_name(&new_layout),
+            workspace: get_ancestor(tree, plan.focused, NodeType::Workspace)
+                .and_then(|ws| ws.name.clone())
+                .unwrap_or_default(),
+            app_id: app_id(plan.focused).to_string(),
+        });
+        pending.commands.push(cmd);
+        if settings.state_file.is_some() {
+            pending.commands.push(format!(
+                "[con_id={0}] mark --add {1}{0}",
+                plan.target.id, STATE_MARK_PREFIX
+            ));
+        }
+    }
+
+    // split hints only apply once
+    if let Some((id, mark)) = plan.hint {
+        pending
+            .commands
+            .push(format!("[con_id={}] unmark {}", id, mark));
+    }
+}
+
+/**
+ * Send the pending commands to sway in a single message and report the ones that failed.
+ * Sway runs `;` separated commands one after another, each with its own criteria.
+ */
+fn flush(
+    conn: &mut Connection,
+    settings: &Settings,
+    state: &mut State,
+    pending: Pending,
+) -> Result<(), String> {
+    if pending.commands.is_empty() {
         return Ok(());
     }
+    let replies = ipc(conn, |conn| conn.run_command(pending.commands.join("; ")))
+        .map_err(|err| format!("run_command() failed: {}", err))?;
+    for (cmd, reply) in pending.commands.iter().zip(&replies) {
+        if let Err(err) = reply {
+            eprintln!("err: `{}` failed: {}", cmd, err);
+        }
+    }
 
-    // if there is a single window in the workspace, always split horizontaly
-    let mut current = parent;
-    while current.nodes.len() == 1 {
-        if current.node_type == NodeType::Workspace {
-            configure_layout(NodeLayout::SplitH, parent, conn);
-            return Ok(());
-        } else {
-            current = get_parent(&tree, current).unwrap();
+    let mut relayouted = false;
+    for relayout in pending.relayouts {
+        // sway stops at a command it cannot parse, so there may be fewer replies than commands
+        if !matches!(replies.get(relayout.command), Some(Ok(()))) {
+            continue;
+        }
+        relayouted = true;
+        state.stats.relayout(relayout.layout);
+        state.last_focus = None;
+        if settings.state_file.is_some() {
+            let mark = format!("{}{}", STATE_MARK_PREFIX, relayout.target);
+            state.remembered.insert(mark, relayout.layout.to_string());
+        }
+        state.issued = Some((relayout.focused, state.clock.now()));
+        state.track(
+            settings,
+            &relayout.workspace,
+            [relayout.focused, relayout.target],
+        );
+        if settings.container_layout_cache {
+            state.cache_layout(relayout.target, relayout.layout);
+        }
+        if settings.double_check {
+            if let Some(warning) = double_check(conn, relayout.target, relayout.layout)? {
+                eprintln!("warn: {}", warning);
+            }
+        }
+        if let Some(hook) = &settings.on_relayout {
+            run_hook(
+                state.shell.as_ref(),
+                hook,
+                relayout.layout,
+                &relayout.workspace,
+                &relayout.app_id,
+            );
         }
     }
 
-    let real_ratio = (focused_node.rect.height as f32) / (focused_node.rect.width as f32);
-    if real_ratio > ratio {
-        configure_layout(NodeLayout::SplitV, parent, conn);
-    } else {
-        configure_layout(NodeLayout::SplitH, parent, conn);
+    if settings.preserve_focus && relayouted {
+        restore_focus(conn, state, pending.focus)?;
+    }
+    Ok(())
+}
+
+/**
+ * Focus the window with the `focus` id again, unless it still has focus
+ */
+fn restore_focus(conn: &mut Connection, state: &mut State, focus: i64) -> Result<(), String> {
+    let tree = get_tree(conn)?;
+    let focused = tree.find_focused_as_ref(|n| n.focused).map(|n| n.id);
+    // the window may have been closed in the meantime
+    if focused == Some(focus) || find_node(&tree, focus).is_none() {
+        return Ok(());
     }
 
+    let cmd = format!("[con_id={}] focus", focus);
+    let replies = ipc(conn, |conn| conn.run_command(&cmd))
+        .map_err(|err| format!("run_command() failed: {}", err))?;
+    if let Some(Err(err)) = replies.first() {
+        eprintln!("err: `{}` failed: {}", cmd, err);
+    }
+    state.issued = Some((focus, state.clock.now()));
     Ok(())
 }
 
 /**
- * Reimplementation of Node::find_focused_as_ref, that takes closure instead of a function ptr
+ * A warning if the container around `target` did not end up with `layout`. Sway reports
+ * success for some commands it then ignores.
  */
-pub fn node_find_focused_as_ref<'a, F>(slf: &'a Node, predicate: F) -> Option<&'a Node>
-where
-    F: Fn(&Node) -> bool,
-{
-    if predicate(slf) {
-        return Some(slf);
+fn double_check(
+    conn: &mut Connection,
+    target: i64,
+    layout: &str,
+) -> Result<Option<String>, String> {
+    let tree = get_tree(conn)?;
+    let actual = find_node(&tree, target)
+        .and_then(|node| get_parent(&tree, node))
+        .map(|parent| layout_name(&parent.layout));
+    Ok((actual != Some(layout)).then(|| {
+        format!(
+            "asked for {} around #{}, but sway reports {}",
+            layout,
+            target,
+            actual.unwrap_or("no such container")
+        )
+    }))
+}
+
+/**
+ * The --decisions-csv row for a decision
+ */
+fn decision_row<'a>(tree: &'a Node, plan: &'a Plan) -> Row<'a> {
+    let name = |node_type| {
+        get_ancestor(tree, plan.focused, node_type)
+            .and_then(|node| node.name.as_deref())
+            .unwrap_or_default()
+    };
+    let (layout, ignored_reason) = match &plan.decision {
+        Decision::Layout(layout) => (layout_name(layout), ""),
+        Decision::Skip(reason) => ("", *reason),
+    };
+    Row {
+        workspace: name(NodeType::Workspace),
+        output: name(NodeType::Output),
+        app_id: app_id(plan.focused),
+        size: plan.size,
+        real_ratio: plan.real_ratio,
+        effective_ratio: plan.ratio,
+        layout,
+        ignored_reason,
+    }
+}
+
+/**
+ * The line a decision is logged to stdout with
+ */
+fn decision_line(format: LogFormat, plan: &Plan) -> String {
+    match (format, &plan.decision) {
+        (LogFormat::Text, Decision::Layout(layout)) => format!(
+            "#{} {:?}: {} (ratio {:.3}, threshold {:.3})",
+            plan.focused.id,
+            app_id(plan.focused),
+            layout_name(layout),
+            plan.real_ratio,
+            plan.ratio
+        ),
+        (LogFormat::Text, Decision::Skip(reason)) => format!(
+            "#{} {:?}: unchanged, {} (ratio {:.3}, threshold {:.3})",
+            plan.focused.id,
+            app_id(plan.focused),
+            reason,
+            plan.real_ratio,
+            plan.ratio
+        ),
+        (LogFormat::Minimal, decision) => {
+            let outcome = match decision {
+                Decision::Layout(layout) => layout_name(layout).to_string(),
+                Decision::Skip(reason) => skip_token(reason),
+            };
+            format!("{:.3} {:.3} {}", plan.real_ratio, plan.ratio, outcome)
+        }
     }
-    if slf.focus.is_empty() {
+}
+
+/**
+ * Log a skip that was decided before there was a decision with ratios to log
+ */
+fn log_skip(settings: &Settings, reason: &str) {
+    if settings.log_format == Some(LogFormat::Minimal) {
+        println!("- - {}", skip_token(reason));
+    }
+}
+
+/**
+ * A skip reason as a single token, so that a --log-format minimal line splits on whitespace
+ */
+fn skip_token(reason: &str) -> String {
+    reason
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/**
+ * The command that configures the layout of the currently focused container, or of the
+ * container with the `target` id. None if nothing needs to change.
+ */
+fn layout_command(new_layout: &NodeLayout, parent: &Node, target: Option<i64>) -> Option<String> {
+    // as an optimization, skip IPC if parent already set correctly. This holds for workspaces
+    // as well, their layout is the one their tiling children are arranged in, which is also
+    // where the next window goes
+    if *new_layout == parent.layout {
         return None;
     }
-    let first = slf.focus[0];
-    for node in &slf.nodes {
-        if node.id == first {
-            return node_find_focused_as_ref(node, predicate);
+
+    let cmd = match new_layout {
+        NodeLayout::Tabbed => "layout tabbed",
+        _ => layout_name(new_layout),
+    };
+    Some(match target {
+        Some(id) => format!("[con_id={}] {}", id, cmd),
+        None => cmd.to_string(),
+    })
+}
+
+/**
+ * Run the user's relayout hook through the shell without waiting for it to finish
+ */
+fn run_hook(shell: &dyn Shell, hook: &str, layout: &str, workspace: &str, app_id: &str) {
+    let env = [
+        ("AT_LAYOUT", layout),
+        ("AT_WORKSPACE", workspace),
+        ("AT_APPID", app_id),
+    ];
+    shell.spawn(hook, &env, "relayout hook");
+}
+
+/// How many times in a row we try to (re)connect to sway before giving up
+const MAX_CONNECT_ATTEMPTS: u32 = 5;
+/// Delay before the first reconnection attempt, grows linearly with every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/**
+ * Locate the IPC socket the same way swayipc does, but report a missing socket as an error
+ * instead of panicking
+ */
+fn socket_path() -> Result<PathBuf, String> {
+    if let Some(path) = env::var_os("I3SOCK").or_else(|| env::var_os("SWAYSOCK")) {
+        return Ok(PathBuf::from(path));
+    }
+    for wm in ["i3", "sway"] {
+        if let Ok(output) = Command::new(wm).arg("--get-socketpath").output() {
+            let path = String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string();
+            if output.status.success() && !path.is_empty() {
+                return Ok(PathBuf::from(path));
+            }
         }
     }
-    for node in &slf.floating_nodes {
-        if node.id == first {
-            return node_find_focused_as_ref(node, predicate);
+    Err("could not find the IPC socket, neither SWAYSOCK nor I3SOCK is set".to_string())
+}
+
+/**
+ * Fetch the tree, explaining once if it can not be decoded
+ */
+fn get_tree(conn: &mut Connection) -> Result<Node, String> {
+    ipc(conn, Connection::get_tree).map_err(|err| {
+        if let Some(warning) =
+            TREE_REPORTED.with(|reported| unreadable_tree_warning(&err, reported))
+        {
+            eprintln!("warn: {}", warning);
+        }
+        "get_tree() failed".to_string()
+    })
+}
+
+/**
+ * The warning for a tree that could not be decoded, unless `reported` says it was given already
+ */
+fn unreadable_tree_warning(err: &swayipc::Error, reported: &Cell<bool>) -> Option<String> {
+    // swayipc knows a fixed set of layouts and node types, a newer sway may have more
+    match err {
+        swayipc::Error::SerdeJson(err) if !reported.replace(true) => Some(format!(
+            "sway sent a tree this version does not understand, is sway newer than autotiling-rs? ({})",
+            err
+        )),
+        _ => None,
+    }
+}
+
+/**
+ * The warning for a plan on a node type or layout we have no rule for, unless `reported` says
+ * that one about the tree was given already. swayipc may know them, but a newer sway may use
+ * them in ways we do not expect.
+ */
+fn unhandled_tree_warning(plan: &Plan, reported: &Cell<bool>) -> Option<String> {
+    let window = matches!(
+        plan.focused.node_type,
+        NodeType::Con | NodeType::FloatingCon
+    );
+    let known_type = window
+        || matches!(
+            plan.focused.node_type,
+            NodeType::Root | NodeType::Output | NodeType::Workspace
+        );
+    // the layouts a window can be in
+    let known_layout = !window
+        || matches!(
+            plan.container.layout,
+            NodeLayout::SplitH | NodeLayout::SplitV | NodeLayout::Stacked | NodeLayout::Tabbed
+        );
+    let what = if !known_type {
+        format!("a node of type {:?}", plan.focused.node_type)
+    } else if !known_layout {
+        format!(
+            "the layout {:?} on #{}",
+            plan.container.layout, plan.container.id
+        )
+    } else {
+        return None;
+    };
+    (!reported.replace(true)).then(|| {
+        format!(
+            "sway sent {}, which this version does not handle, is sway newer than autotiling-rs?",
+            what
+        )
+    })
+}
+
+thread_local! {
+    /// Set once we told the user that we do not fully understand the tree sway sent
+    static TREE_REPORTED: Cell<bool> = const { Cell::new(false) };
+    /// Set when sway did not answer in time, see --ipc-timeout-ms. Only the thread that
+    /// made the request needs to know, which keeps the tests apart.
+    static IPC_TIMED_OUT: Cell<bool> = const { Cell::new(false) };
+}
+
+/**
+ * Make a request on the command connection. Every request goes through here, so that a
+ * timeout is noticed whichever request it hit.
+ */
+fn ipc<T>(
+    conn: &mut Connection,
+    request: impl FnOnce(&mut Connection) -> Result<T, swayipc::Error>,
+) -> Result<T, swayipc::Error> {
+    request(conn).map_err(|err| {
+        note_timeout(&err);
+        err
+    })
+}
+
+/**
+ * Remember if `err` means that sway did not answer within --ipc-timeout-ms
+ */
+fn note_timeout(err: &swayipc::Error) {
+    if let swayipc::Error::Io(err) = err {
+        if matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ) {
+            IPC_TIMED_OUT.with(|timed_out| timed_out.set(true));
         }
     }
-    None
 }
 
-fn get_parent<'a>(tree: &'a Node, current: &'a Node) -> Option<&'a Node> {
-    node_find_focused_as_ref(tree, |n| n.nodes.iter().any(|nn| nn.id == current.id))
+/**
+ * The outputs as sway reports them now
+ */
+fn get_outputs(conn: &mut Connection) -> Result<Outputs, String> {
+    let mut outputs = Outputs::default();
+    outputs.update(
+        ipc(conn, Connection::get_outputs)
+            .map_err(|err| format!("get_outputs() failed: {}", err))?,
+    );
+    Ok(outputs)
 }
 
 /**
- * Determine, whether we should do anything with this window
+ * Connect to sway. Without a timeout, requests wait for as long as sway takes to answer,
+ * which is also what the event connection needs.
  */
-fn should_we_ignore_this_window(focused_node: &swayipc::Node) -> bool {
-    // get info from the focused child node
-    let is_stacked = focused_node.layout == NodeLayout::Stacked;
-    let is_tabbed = focused_node.layout == NodeLayout::Tabbed;
-    let is_floating = focused_node.node_type == NodeType::FloatingCon;
-    let is_full_screen = focused_node.percent.unwrap_or(1.0) > 1.0;
+fn open_connection(path: &Path, timeout: Option<Duration>) -> Result<Connection, String> {
+    open_socket(path, timeout).map(Connection::from)
+}
 
-    is_floating || is_full_screen || is_stacked || is_tabbed
+fn open_socket(path: &Path, timeout: Option<Duration>) -> Result<UnixStream, String> {
+    let stream = UnixStream::connect(path).map_err(|err| {
+        format!(
+            "could not connect to sway at {:?} (is it running?): {}",
+            path, err
+        )
+    })?;
+    stream
+        .set_read_timeout(timeout)
+        .and_then(|()| stream.set_write_timeout(timeout))
+        .map_err(|err| format!("could not set the IPC timeout: {}", err))?;
+    Ok(stream)
 }
 
 /**
- * Configure layout of the currently focused container
+ * How long requests on the command connection may take. With --keep-alive-ms, a half-open
+ * connection must not block the keep-alive request forever, so it gets a timeout of its own
+ * unless --ipc-timeout-ms sets one.
  */
-fn configure_layout(new_layout: NodeLayout, parent: &Node, conn: &mut Connection) {
-    // as an optimization, skip IPC if parent already set correctly
-    if new_layout == parent.layout {
-        return;
+fn command_timeout(settings: &Settings) -> Option<Duration> {
+    [settings.ipc_timeout_ms, settings.keep_alive_ms]
+        .iter()
+        .copied()
+        .find(|&ms| ms > 0)
+        .map(Duration::from_millis)
+}
+
+/**
+ * Open the connections to sway. We need two of them, because once a connection subscribes to
+ * events, sway only sends events over it and it can no longer be used to run commands.
+ * So one connection is used for get_tree() and run_command() and the other one for the events.
+ * The socket of the latter is returned as well, shutting it down ends the event stream.
+ */
+fn connect(settings: &Settings) -> Result<(Connection, EventStream, UnixStream), String> {
+    let path = socket_path()?;
+    let conn = open_connection(&path, command_timeout(settings))?;
+    let socket = open_socket(&path, None)?;
+    subscribe(settings, conn, socket)
+}
+
+/**
+ * Subscribe to the events we handle on `socket`, the event connection next to `conn`
+ */
+fn subscribe(
+    settings: &Settings,
+    conn: Connection,
+    socket: UnixStream,
+) -> Result<(Connection, EventStream, UnixStream), String> {
+    let mut subscriptions = vec![EventType::Window, EventType::Mode, EventType::Workspace];
+    // there is no window event for resizing, but resizing from a key binding shows up
+    // as a binding event
+    if settings.relayout_on_resize {
+        subscriptions.push(EventType::Binding);
     }
+    let events_socket = socket
+        .try_clone()
+        .map_err(|err| format!("could not connect to sway: {}", err))?;
+    let events = Connection::from(socket)
+        .subscribe(subscriptions)
+        .map_err(|err| format!("could not subscribe to sway events: {}", err))?;
+    Ok((conn, events, events_socket))
+}
 
-    let cmd = match new_layout {
-        NodeLayout::SplitV => "splitv",
-        NodeLayout::SplitH => "splith",
-        _ => "nop",
+/**
+ * Read events on a separate thread, so that the main loop can also wake up on its own timers
+ */
+fn spawn_event_reader(events: EventStream) -> Receiver<Result<Event, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for event in events {
+            let failed = event.is_err();
+            let event = event.map_err(|err| format!("lost connection to sway: {}", err));
+            if tx.send(event).is_err() || failed {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// For how long events about a window we just issued a command for count as its echo
+const OWN_COMMAND_ECHO: Duration = Duration::from_millis(200);
+
+/// Resizing fires many events in a row, wait for this long after the last one
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// For how long --container-layout-cache trusts the layouts we set over the tree
+const LAYOUT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// For how long to trust whether the screen is locked, looking walks through all of /proc
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to check for signals while waiting for events
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Errors in a row, each within ERROR_WINDOW of the last, that make --notify-on-error speak up
+const NOTIFY_AFTER_ERRORS: usize = 3;
+const ERROR_WINDOW: Duration = Duration::from_secs(60);
+/// --notify-on-error speaks up at most this often
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(300);
+
+/**
+ * What we remember between events
+ */
+struct State {
+    /// name of the active binding mode
+    mode: String,
+    outputs: Outputs,
+    stats: Stats,
+    /// the commands for the focused window, see --relayout-only-before-new
+    before_new: Option<Pending>,
+    /// when to take another look at a window whose relayout we postponed
+    deferred: Option<Instant>,
+    /// the windows that just appeared and when to lay them out, see --new-window-grace-ms
+    new_windows: BTreeMap<i64, Instant>,
+    clock: Box<dyn Clock>,
+    /// runs --on-relayout and --notify-on-error
+    shell: Box<dyn Shell>,
+    /// window events left to ignore after connecting, see --warmup-events
+    warmup_left: u64,
+    /// the window we last issued a command for and when
+    issued: Option<(i64, Instant)>,
+    /// when to relayout after the last resize, see --relayout-on-resize
+    resized: Option<Instant>,
+    /// reasons already reported, see --explain-startup
+    explained: HashSet<&'static str>,
+    /// layouts we chose by the mark of their container, see --state-file
+    remembered: BTreeMap<String, String>,
+    /// see --decisions-csv
+    decisions: Option<DecisionLog>,
+    /// the window of the last focus event, unless anything happened since
+    last_focus: Option<i64>,
+    /// see --learn-app-layouts
+    learned: Learned,
+    /// when we last looked at a window for a focus event, see --per-container-ms
+    evaluated: HashMap<i64, Instant>,
+    /// whether --relayout-all-on-start is done
+    relaid_out_all: bool,
+    /// when the errors since the last success happened, see --notify-on-error
+    recent_errors: Vec<Instant>,
+    /// when we last showed a notification
+    notified: Option<Instant>,
+    /// whether the screen was locked when we last looked and when that was
+    locked: Option<(bool, Instant)>,
+    /// the layouts we set by the id of the node the command was issued on and when, see
+    /// --container-layout-cache
+    layouts: HashMap<i64, (&'static str, Instant)>,
+    /// the containers we laid out by the name of their workspace and when that last happened,
+    /// see --max-workspaces-tracked
+    workspaces: HashMap<String, (Instant, HashSet<i64>)>,
+}
+
+impl State {
+    fn new(clock: Box<dyn Clock>, shell: Box<dyn Shell>) -> State {
+        State {
+            mode: "default".to_string(),
+            outputs: Outputs::default(),
+            stats: Stats::new(clock.now()),
+            before_new: None,
+            deferred: None,
+            new_windows: BTreeMap::new(),
+            warmup_left: 0,
+            clock,
+            shell,
+            issued: None,
+            resized: None,
+            explained: HashSet::new(),
+            remembered: BTreeMap::new(),
+            decisions: None,
+            last_focus: None,
+            learned: Learned::default(),
+            evaluated: HashMap::new(),
+            relaid_out_all: false,
+            recent_errors: Vec::new(),
+            notified: None,
+            locked: None,
+            layouts: HashMap::new(),
+            workspaces: HashMap::new(),
+        }
+    }
+
+    /**
+     * Catch up with whatever changed while we were not connected
+     */
+    fn connected(&mut self, conn: &mut Connection, settings: &Settings) {
+        self.warmup_left = settings.warmup_events;
+        // sway may have been restarted and given the ids to other containers
+        self.layouts.clear();
+        self.before_new = None;
+        // i3 does not know this request, but it starts in the default mode as well
+        self.mode =
+            ipc(conn, Connection::get_binding_state).unwrap_or_else(|_| "default".to_string());
+        match get_outputs(conn) {
+            Ok(outputs) => self.outputs = outputs,
+            Err(err) => eprintln!("err: {}", err),
+        }
+        if settings.state_file.is_some() {
+            if let Err(err) = self.restore(conn) {
+                eprintln!("err: {}", err);
+            }
+        }
+    }
+
+    /**
+     * Give the remembered containers their layout again and forget the ones that are gone
+     */
+    fn restore(&mut self, conn: &mut Connection) -> Result<(), String> {
+        let tree = get_tree(conn)?;
+        self.remembered
+            .retain(|mark, _| find_marked(&tree, mark).is_some());
+        let commands: Vec<String> = self
+            .remembered
+            .iter()
+            .filter_map(|(mark, layout)| {
+                if !matches!(layout.as_str(), "splith" | "splitv" | "tabbed") {
+                    return None;
+                }
+                // a split command would wrap the container in a new one if it has siblings,
+                // what we remember is the layout of the container it is in
+                let parent = find_marked(&tree, mark).and_then(|node| get_parent(&tree, node))?;
+                if layout_name(&parent.layout) == layout {
+                    return None;
+                }
+                Some(format!("[con_mark=\"{}\"] layout {}", mark, layout))
+            })
+            .collect();
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        let replies = ipc(conn, |conn| conn.run_command(commands.join("; ")))
+            .map_err(|err| format!("run_command() failed: {}", err))?;
+        for (cmd, reply) in commands.iter().zip(&replies) {
+            if let Err(err) = reply {
+                eprintln!("err: `{}` failed: {}", cmd, err);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Whether we recently set `layout` with a command issued on `id`, see --container-layout-cache
+     */
+    fn layout_cached(&self, settings: &Settings, id: i64, layout: &NodeLayout) -> bool {
+        settings.container_layout_cache
+            && self.layouts.get(&id).is_some_and(|&(cached, at)| {
+                cached == layout_name(layout)
+                    && self.clock.now().duration_since(at) < LAYOUT_CACHE_TTL
+            })
+    }
+
+    fn cache_layout(&mut self, id: i64, layout: &'static str) {
+        let now = self.clock.now();
+        self.layouts
+            .retain(|_, &mut (_, at)| now.duration_since(at) < LAYOUT_CACHE_TTL);
+        self.layouts.insert(id, (layout, now));
+    }
+
+    /**
+     * Note that we laid out `ids` on `workspace`, and forget the least recently laid out
+     * workspace once there are more than --max-workspaces-tracked
+     */
+    fn track(&mut self, settings: &Settings, workspace: &str, ids: [i64; 2]) {
+        let now = self.clock.now();
+        // a container that moved belongs to the workspace it was laid out on last
+        for (_, tracked) in self.workspaces.values_mut() {
+            tracked.retain(|id| !ids.contains(id));
+        }
+        let (used, tracked) = self
+            .workspaces
+            .entry(workspace.to_string())
+            .or_insert_with(|| (now, HashSet::new()));
+        *used = now;
+        tracked.extend(ids);
+
+        let max = settings.max_workspaces_tracked.max(1) as usize;
+        while self.workspaces.len() > max {
+            let oldest = self
+                .workspaces
+                .iter()
+                .filter(|(name, _)| name.as_str() != workspace)
+                .min_by_key(|(_, &(used, _))| used)
+                .map(|(name, _)| name.clone());
+            match oldest {
+                Some(name) => self.forget_workspace(&name),
+                None => break,
+            }
+        }
+    }
+
+    /**
+     * Drop what we know about the containers we laid out on `workspace`
+     */
+    fn forget_workspace(&mut self, workspace: &str) {
+        let ids = match self.workspaces.remove(workspace) {
+            Some((_, ids)) => ids,
+            None => return,
+        };
+        for id in ids {
+            self.layouts.remove(&id);
+            self.evaluated.remove(&id);
+            self.remembered
+                .remove(&format!("{}{}", STATE_MARK_PREFIX, id));
+        }
+    }
+
+    /**
+     * Forget on which workspace `id` was laid out, it moved elsewhere
+     */
+    fn untrack(&mut self, id: i64) {
+        for (_, tracked) in self.workspaces.values_mut() {
+            tracked.remove(&id);
+        }
+    }
+
+    /**
+     * The earliest of the deadlines events set, e.g. for --new-window-grace-ms
+     */
+    fn next_deadline(&self) -> Option<Instant> {
+        [
+            self.deferred,
+            self.new_windows.values().min().copied(),
+            self.resized,
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+
+    /**
+     * Whether an event about `id` is most likely caused by the command we just issued for it
+     */
+    fn is_own_echo(&self, id: i64) -> bool {
+        self.issued.is_some_and(|(issued, at)| {
+            issued == id && self.clock.now().duration_since(at) < OWN_COMMAND_ECHO
+        })
+    }
+
+    /**
+     * Record that we did not act before there was a decision to log, `context` says on what
+     */
+    fn skip(
+        &mut self,
+        settings: &Settings,
+        reason: &'static str,
+        context: impl FnOnce() -> String,
+    ) {
+        log_skip(settings, reason);
+        self.stats.skip(reason);
+        self.explain(settings, reason, context);
+    }
+
+    /**
+     * Tell the user why nothing happened, once for every reason
+     */
+    fn explain(
+        &mut self,
+        settings: &Settings,
+        reason: &'static str,
+        context: impl FnOnce() -> String,
+    ) {
+        if settings.explain_startup && self.explained.insert(reason) {
+            eprintln!("info: skipped {}: {}", context(), reason);
+        }
+    }
+
+    /**
+     * Whether the window with `id` was looked at too recently, otherwise it counts as looked at now
+     */
+    fn throttled(&mut self, settings: &Settings, id: i64) -> bool {
+        let now = self.clock.now();
+        let interval = Duration::from_millis(settings.per_container_ms);
+        // only the windows within the interval matter, forget the others
+        self.evaluated
+            .retain(|_, at| now.duration_since(*at) < interval);
+        if self.evaluated.contains_key(&id) {
+            return true;
+        }
+        self.evaluated.insert(id, now);
+        false
+    }
+
+    /**
+     * Report an error, and with --notify-on-error also on the desktop once they pile up
+     */
+    fn report(&mut self, settings: &Settings, err: &str) {
+        eprintln!("err: {}", err);
+        if !settings.notify_on_error {
+            return;
+        }
+
+        let now = self.clock.now();
+        if self
+            .recent_errors
+            .last()
+            .is_some_and(|&last| now.duration_since(last) >= ERROR_WINDOW)
+        {
+            self.recent_errors.clear();
+        }
+        self.recent_errors.push(now);
+        let quiet = !self
+            .notified
+            .is_some_and(|at| now.duration_since(at) < NOTIFY_INTERVAL);
+        if self.recent_errors.len() >= NOTIFY_AFTER_ERRORS && quiet {
+            self.notified = Some(now);
+            self.shell.spawn(
+                &settings.notify_command,
+                &[("AT_ERROR", err)],
+                "error notification",
+            );
+        }
+    }
+
+    /**
+     * Why relayouts are currently suppressed, if they are
+     */
+    fn paused(&mut self, settings: &Settings) -> Option<&'static str> {
+        if settings.pause_in_modes.contains(&self.mode) {
+            Some("mode in --pause-in-modes")
+        } else if settings.pause_when_locked && self.locked(settings) {
+            Some("screen locked")
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Whether --lock-app is running, as looked up at most once every LOCK_CHECK_INTERVAL
+     */
+    fn locked(&mut self, settings: &Settings) -> bool {
+        let now = self.clock.now();
+        match self.locked {
+            Some((locked, at)) if now.duration_since(at) < LOCK_CHECK_INTERVAL => locked,
+            _ => {
+                let locked = lock::is_running(&settings.lock_app);
+                self.locked = Some((locked, now));
+                locked
+            }
+        }
+    }
+}
+
+/**
+ * Send the commands decided on for the window the new window with `id` appeared next to, see
+ * --relayout-only-before-new
+ */
+fn send_before_new(conn: &mut Connection, settings: &Settings, state: &mut State, id: i64) {
+    let mut pending = match state.before_new.take() {
+        Some(pending) => pending,
+        None => return,
     };
-    conn.run_command(cmd).unwrap();
+    if let Some(reason) = state.paused(settings) {
+        state.skip(settings, reason, || format!("#{}", id));
+        return;
+    }
+    // the new window has the focus now
+    pending.focus = id;
+    match flush(conn, settings, state, pending) {
+        Ok(()) => state.recent_errors.clear(),
+        Err(err) => {
+            state.stats.errors += 1;
+            state.report(settings, &err);
+        }
+    }
+}
+
+fn relayout(conn: &mut Connection, settings: &Settings, state: &mut State, deferred: bool) {
+    if let Some(reason) = state.paused(settings) {
+        log_skip(settings, reason);
+        let mode = state.mode.clone();
+        state.explain(settings, reason, || format!("relayout in mode {:?}", mode));
+        return;
+    }
+    match switch_splitting(conn, settings, state, deferred) {
+        Ok(()) => state.recent_errors.clear(),
+        Err(err) => {
+            // nothing was decided, so the next focus event must not count as a repeat
+            state.last_focus = None;
+            state.stats.errors += 1;
+            state.report(settings, &err);
+        }
+    }
 }
 
-#[derive(Parser)]
-#[clap(version, author, about)]
-struct Cli {
-    /// Activate autotiling only on this workspace. More than one workspace may be specified.
-    #[clap(long, short = 'w')]
-    workspace: Vec<i32>,
+/**
+ * Handle events until the connection to sway breaks, which is reported as an error, or until
+ * we are asked to terminate
+ */
+fn handle_events(
+    conn: &mut Connection,
+    events: &Receiver<Result<Event, String>>,
+    settings: &Settings,
+    state: &mut State,
+) -> Result<(), String> {
+    state.connected(conn, settings);
+    let mut timers = Timers::new(settings, state.clock.now());
+
+    loop {
+        if signal::terminate_requested() {
+            return Ok(());
+        }
+        relayout_all_on_start(conn, settings, state);
+
+        let now = state.clock.now();
+        let wakeup = timers
+            .next(settings, state)
+            .map_or(now + SIGNAL_CHECK_INTERVAL, |timer| {
+                timer.min(now + SIGNAL_CHECK_INTERVAL)
+            });
+        let event = events.recv_timeout(wakeup.saturating_duration_since(now));
+
+        match event {
+            Ok(Ok(event)) => {
+                state.stats.events += 1;
+                handle_event(conn, settings, state, event);
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(RecvTimeoutError::Disconnected) => return Err("event stream ended".to_string()),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
 
-    #[clap(long, short = 'r', default_value_t = 0.4)]
-    ratio: f32,
+        run_timers(
+            conn,
+            settings,
+            state,
+            &mut timers,
+            signal::relayout_requested(),
+        )?;
+    }
 }
 
-fn main() -> Result<(), std::io::Error> {
-    let args = Cli::parse();
+/**
+ * Relayout everything once, see --relayout-all-on-start. A pause postpones it until the pause
+ * is over, after a reconnect the layouts are as tidy as we left them.
+ */
+fn relayout_all_on_start(conn: &mut Connection, settings: &Settings, state: &mut State) {
+    if !settings.relayout_all_on_start || state.relaid_out_all || state.paused(settings).is_some() {
+        return;
+    }
+    state.relaid_out_all = true;
+    if let Err(err) = relayout_workspaces(conn, settings, state, true) {
+        state.stats.errors += 1;
+        state.report(settings, &err);
+    }
+}
+
+/// When the regular checks of handle_events are due next
+struct Timers {
+    next_poll: Instant,
+    next_keep_alive: Instant,
+}
+
+impl Timers {
+    fn new(settings: &Settings, now: Instant) -> Timers {
+        Timers {
+            next_poll: now + Duration::from_millis(settings.poll_ms),
+            next_keep_alive: now + Duration::from_millis(settings.keep_alive_ms),
+        }
+    }
 
-    let mut conn = Connection::new().unwrap();
-    for event in Connection::new()
-        .unwrap()
-        .subscribe(&[EventType::Window])
-        .unwrap()
+    /**
+     * When run_timers() has something to do next, if at all
+     */
+    fn next(&self, settings: &Settings, state: &State) -> Option<Instant> {
+        [
+            (settings.poll_ms > 0).then_some(self.next_poll),
+            (settings.keep_alive_ms > 0).then_some(self.next_keep_alive),
+            state.next_deadline(),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+}
+
+/**
+ * Do what is due by now, `relayout_requested` says whether SIGUSR2 came in. An error means
+ * that the connection to sway has to start over.
+ */
+fn run_timers(
+    conn: &mut Connection,
+    settings: &Settings,
+    state: &mut State,
+    timers: &mut Timers,
+    relayout_requested: bool,
+) -> Result<(), String> {
+    let now = state.clock.now();
+    // after a suspend the event connection can be dead without us ever hearing about it,
+    // a dead command connection is the one thing we can notice
+    if settings.keep_alive_ms > 0 && now >= timers.next_keep_alive {
+        timers.next_keep_alive = now + Duration::from_millis(settings.keep_alive_ms);
+        ipc(conn, Connection::get_version)
+            .map_err(|err| format!("sway stopped answering: {}", err))?;
+    }
+    if state.deferred.is_some_and(|deferred| now >= deferred) {
+        state.deferred = None;
+        relayout(conn, settings, state, true);
+    }
+    let mut due: Vec<(Instant, i64)> = state
+        .new_windows
+        .iter()
+        .filter(|&(_, &until)| now >= until)
+        .map(|(&id, &until)| (until, id))
+        .collect();
+    due.sort_unstable();
+    for (_, id) in due {
+        state.new_windows.remove(&id);
+        if state.paused(settings).is_none() {
+            if let Err(err) = relayout_window(conn, settings, state, id) {
+                state.stats.errors += 1;
+                state.report(settings, &err);
+            }
+        }
+    }
+    if relayout_requested && state.paused(settings).is_none() {
+        if let Err(err) = relayout_workspaces(conn, settings, state, false) {
+            state.stats.errors += 1;
+            state.report(settings, &err);
+        }
+    }
+    if state.resized.is_some_and(|resized| now >= resized) {
+        state.resized = None;
+        relayout(conn, settings, state, false);
+    }
+    // re-check even without an event, sway does not tell us about everything
+    // that changes the geometry, e.g. resizing. Unless only new windows are waited for.
+    if settings.poll_ms > 0 && now >= timers.next_poll {
+        timers.next_poll = now + Duration::from_millis(settings.poll_ms);
+        if !settings.relayout_only_before_new {
+            relayout(conn, settings, state, false);
+        }
+    }
+    // a late answer would be taken for the answer to our next request, start over
+    if IPC_TIMED_OUT.with(|timed_out| timed_out.replace(false)) {
+        return Err(format!(
+            "sway did not answer within {}ms",
+            command_timeout(settings).unwrap_or_default().as_millis()
+        ));
+    }
+    Ok(())
+}
+
+fn handle_event(conn: &mut Connection, settings: &Settings, state: &mut State, event: Event) {
+    if !matches!(&event, Event::Window(e) if matches!(e.change, WindowChange::Focus)) {
+        state.last_focus = None;
+    }
+    // closing and moving windows rearranges containers behind the cache's back
+    if matches!(&event, Event::Window(e) if matches!(e.change, WindowChange::Close | WindowChange::Move))
     {
-        match event.unwrap() {
-            Event::Window(e) => {
-                if let WindowChange::Focus = e.change {
-                    // We can not use the e.container because the data is stale.
-                    // If we compare that node data with the node given from get_tree() after we
-                    // delete a node we find that the e.container.rect.height and e.container.rect.width are stale,
-                    // and therefore we make the wrong decision on which layout our next window should be.
-                    // Refer to https://github.com/swaywm/sway/issues/5873
-                    if let Err(err) = switch_splitting(&mut conn, args.ratio) {
-                        eprintln!("err: {}", err);
+        state.layouts.clear();
+    }
+    // the mark of a closed window is gone with it
+    if let Event::Window(e) = &event {
+        match e.change {
+            WindowChange::Close => {
+                let mark = format!("{}{}", STATE_MARK_PREFIX, e.container.id);
+                state.remembered.remove(&mark);
+                state.untrack(e.container.id);
+            }
+            // emptying the workspace it left must not forget it
+            WindowChange::Move => state.untrack(e.container.id),
+            _ => {}
+        }
+    }
+    match event {
+        Event::Mode(e) => state.mode = e.change,
+        // sway removes a workspace once it is empty and not focused, its containers are gone
+        Event::Workspace(e) if e.change == WorkspaceChange::Empty => {
+            if let Some(name) = e.current.and_then(|workspace| workspace.name) {
+                state.forget_workspace(&name);
+            }
+        }
+        Event::Workspace(e) => {
+            // sway creates and moves workspaces when outputs are plugged in or out
+            if matches!(
+                e.change,
+                WorkspaceChange::Init | WorkspaceChange::Move | WorkspaceChange::Reload
+            ) {
+                match get_outputs(conn) {
+                    Ok(outputs) => state.outputs = outputs,
+                    Err(err) => eprintln!("err: {}", err),
+                }
+            }
+        }
+        // sway may still be settling right after we connected
+        Event::Window(_) if state.warmup_left > 0 => {
+            log_skip(settings, "within --warmup-events");
+            state.warmup_left -= 1;
+        }
+        // sway reports the effects of our own split commands as window events too
+        Event::Window(e)
+            if settings.no_act_on_own_commands && state.is_own_echo(e.container.id) =>
+        {
+            let id = e.container.id;
+            state.skip(settings, "echo of our own command", || format!("#{}", id));
+        }
+        Event::Window(e) => {
+            if e.change == WindowChange::New {
+                if settings.new_window_grace_ms > 0 {
+                    let until =
+                        state.clock.now() + Duration::from_millis(settings.new_window_grace_ms);
+                    state.new_windows.insert(e.container.id, until);
+                } else if settings.relayout_only_before_new {
+                    send_before_new(conn, settings, state, e.container.id);
+                }
+            }
+            if let WindowChange::Focus = e.change {
+                let id = e.container.id;
+                // focusing the same window again without anything happening in between can
+                // not change the decision, the id in the event is reliable even though the
+                // rest is not
+                if !settings.no_skip_same_focus && state.last_focus == Some(id) {
+                    state.skip(settings, "same window focused again", || format!("#{}", id));
+                    return;
+                }
+                state.last_focus = Some(id);
+                // the window gets laid out once its grace period is over
+                if state.new_windows.contains_key(&id) {
+                    state.skip(settings, "new window within --new-window-grace-ms", || {
+                        format!("#{}", id)
+                    });
+                    return;
+                }
+                if settings.per_container_ms > 0 && state.throttled(settings, id) {
+                    state.skip(settings, "focused again within --per-container-ms", || {
+                        format!("#{}", id)
+                    });
+                    return;
+                }
+                // We can not use the e.container because the data is stale.
+                // If we compare that node data with the node given from get_tree() after we
+                // delete a node we find that the e.container.rect.height and e.container.rect.width are stale,
+                // and therefore we make the wrong decision on which layout our next window should be.
+                // Refer to https://github.com/swaywm/sway/issues/5873
+                relayout(conn, settings, state, false);
+            }
+        }
+        Event::Binding(e) => {
+            let resizing = e
+                .binding
+                .command
+                .split([';', ','])
+                .any(|command| command.trim_start().starts_with("resize"));
+            // like focus, a resize only matters for the next window
+            if resizing && !settings.relayout_only_before_new {
+                state.resized = Some(state.clock.now() + RESIZE_DEBOUNCE);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/**
+ * Wait for a single focus event, print what we would do about it to `out` and stop
+ */
+fn self_test(
+    conn: &mut Connection,
+    events: impl IntoIterator<Item = Result<Event, swayipc::Error>>,
+    settings: &Settings,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let outputs = get_outputs(conn)?;
+    let write_err = |err: io::Error| err.to_string();
+    writeln!(out, "connected to sway, waiting for a focus event...").map_err(write_err)?;
+    for event in events {
+        match event.map_err(|err| format!("lost connection to sway: {}", err))? {
+            Event::Window(e) if e.change == WindowChange::Focus => {
+                let tree = get_tree(conn)?;
+                let plan = decide_layout(&tree, settings, &outputs, &mut Trace::default())?;
+                writeln!(out, "focused: {}", describe_node(plan.focused)).map_err(write_err)?;
+                writeln!(out, "parent:  {}", describe_node(plan.parent)).map_err(write_err)?;
+                writeln!(
+                    out,
+                    "ratio:   {:.3} (threshold {})",
+                    plan.real_ratio, plan.ratio
+                )
+                .map_err(write_err)?;
+                match plan.decision {
+                    Decision::Layout(layout) => {
+                        writeln!(out, "layout:  {}", layout_name(&layout))
                     }
+                    Decision::Skip(reason) => writeln!(out, "layout:  unchanged, {}", reason),
                 }
+                .map_err(write_err)?;
+                return Ok(());
             }
-            _ => unreachable!(),
+            _ => {}
         }
     }
+    Err("event stream ended".to_string())
+}
 
+/**
+ * Explain how the focused window would be laid out, without changing anything. The tree is
+ * read from stdin with `from_stdin` and fetched from sway otherwise, `steps` adds the reasoning.
+ */
+fn why(settings: &Settings, from_stdin: bool, steps: bool) -> Result<(), String> {
+    let (tree, outputs) = if from_stdin {
+        // without sway there are no output sizes, so --auto-ratio falls back to --ratio
+        let tree = serde_json::from_reader(io::stdin().lock())
+            .map_err(|err| format!("could not read a tree from stdin: {}", err))?;
+        (tree, Outputs::default())
+    } else {
+        let mut conn = open_connection(&socket_path()?, None)?;
+        let outputs = get_outputs(&mut conn)?;
+        (get_tree(&mut conn)?, outputs)
+    };
+    let mut trace = if steps {
+        Trace::enabled()
+    } else {
+        Trace::default()
+    };
+    let plan = decide_layout(&tree, settings, &outputs, &mut trace)?;
+
+    if steps {
+        println!("focused: {}", describe_node(plan.focused));
+        println!("parent:  {}", describe_node(plan.parent));
+        for (i, step) in trace.steps().iter().enumerate() {
+            println!("{:>2}. {}", i + 1, step);
+        }
+    }
+    match plan.decision {
+        Decision::Layout(layout) if layout == plan.container.layout => {
+            println!(
+                "=> {}, already set on #{}",
+                layout_name(&layout),
+                plan.container.id
+            )
+        }
+        Decision::Layout(layout) => {
+            println!("=> {} on #{}", layout_name(&layout), plan.container.id)
+        }
+        Decision::Skip(reason) => println!("=> unchanged, {}", reason),
+    }
     Ok(())
 }
+
+/**
+ * Short description of a window for messages, e.g. `#10 "kitty" on workspace 2`
+ */
+fn describe_window(tree: &Node, window: &Node) -> String {
+    let workspace = get_ancestor(tree, window, NodeType::Workspace)
+        .and_then(|ws| ws.name.as_deref())
+        .unwrap_or("none");
+    format!(
+        "#{} {:?} on workspace {}",
+        window.id,
+        app_id(window),
+        workspace
+    )
+}
+
+fn describe_node(node: &Node) -> String {
+    format!(
+        "#{} {:?} {:?} app_id={:?} {}x{} layout={:?}",
+        node.id,
+        node.node_type,
+        node.name.as_deref().unwrap_or_default(),
+        app_id(node),
+        node.rect.width,
+        node.rect.height,
+        node.layout
+    )
+}
+
+fn main() {
+    let args = Cli::parse();
+    if let Some(shell) = args.generate_completions {
+        if let Err(err) = completions::generate(shell, &Cli::command(), &mut io::stdout()) {
+            eprintln!("err: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    let settings = Settings::load(&args).unwrap_or_else(|err| {
+        eprintln!("err: {}", err);
+        process::exit(1);
+    });
+
+    if args.check_config {
+        println!("{:#?}", settings);
+        return;
+    }
+
+    if args.why || args.from_stdin {
+        if let Err(err) = why(&settings, args.from_stdin, args.why) {
+            eprintln!("err: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.simulate {
+        if let Err(err) = simulate::run(&settings, path, &mut io::stdout()) {
+            eprintln!("err: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.self_test {
+        let result = connect(&settings).and_then(|(mut conn, events, _)| {
+            self_test(&mut conn, events, &settings, &mut io::stdout())
+        });
+        if let Err(err) = result {
+            eprintln!("err: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if settings.stats_on_exit || settings.state_file.is_some() {
+        signal::catch_terminate();
+    }
+    signal::catch_relayout();
+
+    let mut state = State::new(Box::new(SystemClock), Box::new(SystemShell));
+    if let Some(path) = &settings.decisions_csv {
+        state.decisions = Some(DecisionLog::open(path).unwrap_or_else(|err| {
+            eprintln!("err: {}", err);
+            process::exit(1);
+        }));
+    }
+    if let Some(path) = &settings.state_file {
+        state.remembered = persist::load(path).unwrap_or_else(|err| {
+            eprintln!("warn: starting without the remembered layouts: {}", err);
+            BTreeMap::new()
+        });
+    }
+    let result = run(&settings, &mut state, || connect(&settings));
+    save_state(&settings, &state);
+    if let Err(err) = result {
+        eprintln!("err: {}", err);
+        process::exit(1);
+    }
+    if settings.stats_on_exit {
+        eprintln!("{}", state.stats.summary(state.clock.now()));
+    }
+}
+
+/**
+ * Handle events until we are asked to stop, reconnecting with `connect` whenever sway goes
+ * away. Gives up once connecting failed MAX_CONNECT_ATTEMPTS times in a row.
+ */
+fn run(
+    settings: &Settings,
+    state: &mut State,
+    mut connect: impl FnMut() -> Result<(Connection, EventStream, UnixStream), String>,
+) -> Result<(), String> {
+    let mut failed_attempts = 0;
+    loop {
+        let err = match connect() {
+            Ok((mut conn, events, events_socket)) => {
+                failed_attempts = 0;
+                let events = spawn_event_reader(events);
+                let result = handle_events(&mut conn, &events, settings, state);
+                // a half-open event connection would keep its reader thread blocked forever
+                let _ = events_socket.shutdown(Shutdown::Both);
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(err) => err,
+                }
+            }
+            Err(err) => err,
+        };
+        state.report(settings, &err);
+
+        failed_attempts += 1;
+        if failed_attempts >= MAX_CONNECT_ATTEMPTS {
+            return Err(format!("giving up after {} attempts", failed_attempts));
+        }
+        state.clock.sleep(RECONNECT_DELAY * failed_attempts);
+        state.stats.reconnects += 1;
+    }
+}
+
+fn save_state(settings: &Settings, state: &State) {
+    if let Some(path) = &settings.state_file {
+        if let Err(err) = persist::save(path, &state.remembered) {
+            eprintln!("err: {}", err);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use swayipc::Output;
+
+/**
+ * Geometry of the active outputs. Sway does not tell us about outputs in the window events,
+ * so this is fetched once and refreshed whenever the set of outputs may have changed.
+ */
+#[derive(Debug, Default)]
+pub struct Outputs {
+    /// width and height in logical pixels, keyed by output name
+    sizes: HashMap<String, (i32, i32)>,
+    /// width and height in physical pixels as the output is oriented, keyed by output name
+    physical_sizes: HashMap<String, (i32, i32)>,
+}
+
+impl Outputs {
+    /**
+     * Forget what we knew and remember the active ones of `outputs`
+     */
+    pub fn update(&mut self, outputs: Vec<Output>) {
+        let outputs: Vec<_> = outputs.into_iter().filter(|o| o.active).collect();
+        self.sizes = outputs
+            .iter()
+            .map(|o| (o.name.clone(), (o.rect.width, o.rect.height)))
+            .collect();
+        self.physical_sizes = outputs
+            .iter()
+            .map(|o| {
+                // the logical size is rounded after scaling, the mode is not. Without a mode,
+                // e.g. on headless outputs, scale the logical size back up.
+                let size = match &o.current_mode {
+                    Some(mode) if rotated(o.transform.as_deref()) => (mode.height, mode.width),
+                    Some(mode) => (mode.width, mode.height),
+                    None => {
+                        let scale = o.scale.unwrap_or(1.0);
+                        let scaled = |logical: i32| (logical as f64 * scale).round() as i32;
+                        (scaled(o.rect.width), scaled(o.rect.height))
+                    }
+                };
+                (o.name.clone(), size)
+            })
+            .collect();
+    }
+
+    /**
+     * height/width of the output in logical or `physical` pixels, None for unknown or
+     * degenerate outputs
+     */
+    pub fn aspect(&self, name: &str, physical: bool) -> Option<f32> {
+        let sizes = if physical {
+            &self.physical_sizes
+        } else {
+            &self.sizes
+        };
+        match sizes.get(name) {
+            Some(&(width, height)) if width > 0 && height > 0 => Some(height as f32 / width as f32),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Whether an output `transform` turns the mode on its side
+ */
+fn rotated(transform: Option<&str>) -> bool {
+    matches!(transform, Some("90" | "270" | "flipped-90" | "flipped-270"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::testing::{output_info, outputs};
+
+    #[test]
+    fn physical_size_of_a_scaled_output() {
+        // 1366x768 at 1.3 is 1775.8x998.4 pixels, the mode has 1776x998
+        let outputs = outputs(vec![output_info("eDP-1", 1366, 768, 1.3)]);
+        assert_eq!(outputs.aspect("eDP-1", false), Some(768.0 / 1366.0));
+        assert_eq!(outputs.aspect("eDP-1", true), Some(998.0 / 1776.0));
+        assert_eq!(outputs.aspect("DP-1", true), None);
+    }
+
+    #[test]
+    fn physical_size_of_a_rotated_output() {
+        let mut rotated = output_info("DP-1", 1080, 1920, 1.0);
+        rotated["transform"] = json!("90");
+        rotated["current_mode"] = json!({ "width": 1920, "height": 1080, "refresh": 60000 });
+        let outputs = outputs(vec![rotated]);
+        assert_eq!(outputs.aspect("DP-1", true), Some(1920.0 / 1080.0));
+        assert_eq!(outputs.aspect("DP-1", false), Some(1920.0 / 1080.0));
+    }
+}
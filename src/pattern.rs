@@ -0,0 +1,327 @@
+//! A small regular expression matcher for matching app ids. It understands the commonly used
+//! subset of the regex syntax: literals, `.`, character classes like `[a-z]` or `[^0-9]`,
+//! the `*`, `+` and `?` repetitions, the `^` and `$` anchors, `|` alternatives and `\` escapes.
+//! Groups are not supported.
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => *expected == c,
+            Atom::Any => true,
+            Atom::Class { negated, ranges } => {
+                ranges.iter().any(|&(low, high)| (low..=high).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone)]
+struct Branch {
+    anchored_start: bool,
+    anchored_end: bool,
+    pieces: Vec<(Atom, Repeat)>,
+}
+
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    branches: Vec<Branch>,
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, String> {
+        let branches = split_alternatives(source)
+            .into_iter()
+            .map(parse_branch)
+            .collect::<Result<_, _>>()
+            .map_err(|err| format!("invalid pattern {:?}: {}", source, err))?;
+        Ok(Pattern {
+            source: source.to_string(),
+            branches,
+        })
+    }
+
+    /**
+     * Whether the pattern matches anywhere in `text`
+     */
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.branches
+            .iter()
+            .any(|branch| match_branch(branch, &text))
+    }
+}
+
+fn split_alternatives(source: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut in_class = false;
+    for (i, c) in source.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '|' if !in_class => {
+                parts.push(&source[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+fn parse_branch(source: &str) -> Result<Branch, String> {
+    let mut chars = source.chars().peekable();
+    let anchored_start = chars.peek() == Some(&'^');
+    if anchored_start {
+        chars.next();
+    }
+
+    let mut pieces: Vec<(Atom, Repeat)> = Vec::new();
+    let mut anchored_end = false;
+    while let Some(c) = chars.next() {
+        if anchored_end {
+            return Err("`$` must be at the end".to_string());
+        }
+        let atom = match c {
+            '$' => {
+                anchored_end = true;
+                continue;
+            }
+            '*' | '+' | '?' => {
+                let repeat = match c {
+                    '*' => Repeat::ZeroOrMore,
+                    '+' => Repeat::OneOrMore,
+                    _ => Repeat::ZeroOrOne,
+                };
+                match pieces.last_mut() {
+                    Some((_, last @ Repeat::One)) => *last = repeat,
+                    _ => return Err(format!("nothing to repeat before `{}`", c)),
+                }
+                continue;
+            }
+            '(' | ')' | '{' | '}' => return Err(format!("`{}` is not supported", c)),
+            '.' => Atom::Any,
+            '\\' => Atom::Char(chars.next().ok_or("trailing `\\`")?),
+            '[' => {
+                let negated = chars.peek() == Some(&'^');
+                if negated {
+                    chars.next();
+                }
+                let mut ranges = Vec::new();
+                loop {
+                    let low = match chars.next() {
+                        Some(']') if !ranges.is_empty() => break,
+                        Some('\\') => chars.next().ok_or("trailing `\\`")?,
+                        Some(c) => c,
+                        None => return Err("unterminated character class".to_string()),
+                    };
+                    let high = if chars.peek() == Some(&'-') {
+                        chars.next();
+                        match chars.next() {
+                            Some(']') => {
+                                // a trailing `-` is just a literal
+                                ranges.push((low, low));
+                                ranges.push(('-', '-'));
+                                break;
+                            }
+                            Some('\\') => chars.next().ok_or("trailing `\\`")?,
+                            Some(c) => c,
+                            None => return Err("unterminated character class".to_string()),
+                        }
+                    } else {
+                        low
+                    };
+                    if high < low {
+                        return Err(format!("invalid range `{}-{}`", low, high));
+                    }
+                    ranges.push((low, high));
+                }
+                Atom::Class { negated, ranges }
+            }
+            c => Atom::Char(c),
+        };
+        pieces.push((atom, Repeat::One));
+    }
+
+    Ok(Branch {
+        anchored_start,
+        anchored_end,
+        pieces,
+    })
+}
+
+/**
+ * Whether `branch` matches somewhere in `text`. Instead of backtracking, which takes exponential
+ * time on patterns like `^a*a*a*b`, every position the pieces so far can end at is followed at
+ * once, from every possible start. That bounds the work by pieces x length² steps.
+ */
+fn match_branch(branch: &Branch, text: &[char]) -> bool {
+    // the positions in `text` the pieces so far can end at
+    let mut reached = vec![!branch.anchored_start; text.len() + 1];
+    reached[0] = true;
+
+    for (atom, repeat) in &branch.pieces {
+        let (min, max) = match repeat {
+            Repeat::One => (1, 1),
+            Repeat::ZeroOrOne => (0, 1),
+            Repeat::ZeroOrMore => (0, usize::MAX),
+            Repeat::OneOrMore => (1, usize::MAX),
+        };
+        let mut next = vec![false; text.len() + 1];
+        for start in (0..=text.len()).filter(|&start| reached[start]) {
+            let available = text[start..]
+                .iter()
+                .take_while(|&&c| atom.matches(c))
+                .count();
+            for n in min..=available.min(max) {
+                next[start + n] = true;
+            }
+        }
+        reached = next;
+    }
+
+    if branch.anchored_end {
+        reached[text.len()]
+    } else {
+        reached.contains(&true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literals_and_any() {
+        assert!(matches("kitty", "kitty"));
+        assert!(matches("itt", "kitty"));
+        assert!(!matches("kitty", "kit"));
+        assert!(matches("k.t", "kit"));
+        assert!(!matches("k.t", "kt"));
+        assert!(matches("", "anything"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("^[a-c]+$", "abcabc"));
+        assert!(!matches("^[a-c]+$", "abcd"));
+        assert!(matches("^[^0-9]+$", "firefox"));
+        assert!(!matches("^[^0-9]+$", "app2"));
+        assert!(matches("^[a-]$", "-"));
+        assert!(matches("^[]]$", "]"));
+        assert!(matches("^[\\]x]+$", "]x]"));
+        assert!(matches("^[._-]$", "_"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("^foot", "footclient"));
+        assert!(!matches("^client", "footclient"));
+        assert!(matches("client$", "footclient"));
+        assert!(!matches("foot$", "footclient"));
+        assert!(matches("^$", ""));
+        assert!(!matches("^$", "x"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(matches("^foot$|^kitty$", "kitty"));
+        assert!(matches("^foot$|^kitty$", "foot"));
+        assert!(!matches("^foot$|^kitty$", "footkitty"));
+        // a `|` inside a class or escaped is just a character
+        assert!(matches("^[|]$", "|"));
+        assert!(matches("^a\\|b$", "a|b"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("^org\\.gnome\\.Nautilus$", "org.gnome.Nautilus"));
+        assert!(!matches("^org\\.gnome$", "orgxgnome"));
+        assert!(matches("\\$", "a$b"));
+        assert!(matches("^\\^", "^x"));
+    }
+
+    #[test]
+    fn repetitions_backtrack() {
+        assert!(matches("^a*ab$", "aaab"));
+        assert!(matches("^.*term$", "xterm"));
+        assert!(matches("^a+a+$", "aa"));
+        assert!(!matches("^a+a+$", "a"));
+        assert!(matches("^colou?r$", "color"));
+        assert!(matches("^colou?r$", "colour"));
+        assert!(!matches("^colou?r$", "colouur"));
+        assert!(matches("^.*-.*-.*$", "a-b-c"));
+    }
+
+    #[test]
+    fn stacked_repetitions_take_no_exponential_time() {
+        // backtracking would try every way of splitting the a's among the repetitions
+        let pattern = format!("^{}b", "a*".repeat(30));
+        let text = "a".repeat(200);
+        assert!(!matches(&pattern, &text));
+        assert!(matches(&pattern, &format!("{}b", text)));
+        assert!(!matches(&format!("{}c|x", "a+".repeat(30)), &text));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let err = |pattern| Pattern::new(pattern).unwrap_err();
+        assert_eq!(
+            err("*a"),
+            "invalid pattern \"*a\": nothing to repeat before `*`"
+        );
+        assert_eq!(
+            err("a**"),
+            "invalid pattern \"a**\": nothing to repeat before `*`"
+        );
+        assert_eq!(err("(a)"), "invalid pattern \"(a)\": `(` is not supported");
+        assert_eq!(
+            err("a$b"),
+            "invalid pattern \"a$b\": `$` must be at the end"
+        );
+        assert_eq!(
+            err("[a"),
+            "invalid pattern \"[a\": unterminated character class"
+        );
+        assert_eq!(
+            err("[z-a]"),
+            "invalid pattern \"[z-a]\": invalid range `z-a`"
+        );
+        assert_eq!(err("a\\"), "invalid pattern \"a\\\\\": trailing `\\`");
+    }
+}
@@ -0,0 +1,28 @@
+//! The layouts we chose, kept across restarts with --state-file. Every container we configure
+//! gets a mark, the file maps these marks to the layout we gave the container.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Marks naming the containers we remember, followed by the con_id
+pub const STATE_MARK_PREFIX: &str = "at:state:";
+
+/**
+ * Read the remembered layouts, a missing file means there are none
+ */
+pub fn load(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(format!("could not read {}: {}", path.display(), err)),
+    };
+    serde_json::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn save(path: &Path, layouts: &BTreeMap<String, String>) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(layouts).map_err(|err| err.to_string())?;
+    fs::write(path, text + "\n")
+        .map_err(|err| format!("could not write {}: {}", path.display(), err))
+}
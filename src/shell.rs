@@ -0,0 +1,52 @@
+//! The user's shell commands, see --on-relayout and --notify-on-error. They go through a
+//! [`Shell`], so that the tests and --simulate can see what would have been run.
+
+use std::cell::RefCell;
+use std::process::Command;
+use std::rc::Rc;
+use std::thread;
+
+pub trait Shell {
+    /**
+     * Run `command` with `env` without waiting for it, `what` names it in error messages
+     */
+    fn spawn(&self, command: &str, env: &[(&str, &str)], what: &str);
+}
+
+/// `sh -c`
+pub struct SystemShell;
+
+impl Shell for SystemShell {
+    fn spawn(&self, command: &str, env: &[(&str, &str)], what: &str) {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().copied())
+            .spawn();
+
+        match child {
+            // reap the child in the background so that it does not linger as a zombie
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => eprintln!("err: failed to spawn {}: {}", what, err),
+        }
+    }
+}
+
+/// The commands a [`RecordingShell`] was asked to run, with their environment
+pub type Spawned = Rc<RefCell<Vec<(String, Vec<(String, String)>)>>>;
+
+/// A shell that only remembers what it was asked to run
+#[derive(Default)]
+pub struct RecordingShell(pub Spawned);
+
+impl Shell for RecordingShell {
+    fn spawn(&self, command: &str, env: &[(&str, &str)], _what: &str) {
+        let env = env
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self.0.borrow_mut().push((command.to_string(), env));
+    }
+}
@@ -0,0 +1,113 @@
+//! Just enough signal handling to shut down cleanly and to relayout on request. The handlers
+//! only set flags, the main loop checks them regularly.
+
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+/// The only one of ours whose number differs between the systems sway runs on, None where we do
+/// not know it. There SIGUSR2 keeps its default action.
+const SIGUSR2: Option<c_int> = if cfg!(all(
+    target_os = "linux",
+    any(
+        target_arch = "mips",
+        target_arch = "mips32r6",
+        target_arch = "mips64",
+        target_arch = "mips64r6"
+    )
+)) {
+    Some(17)
+} else if cfg!(any(
+    all(
+        target_os = "linux",
+        any(target_arch = "sparc", target_arch = "sparc64")
+    ),
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    target_os = "macos"
+)) {
+    Some(31)
+} else if cfg!(any(target_os = "solaris", target_os = "illumos")) {
+    Some(17)
+} else if cfg!(target_os = "linux") {
+    Some(12)
+} else {
+    None
+};
+
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+static RELAYOUT: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    #[cfg(test)]
+    fn raise(signum: c_int) -> c_int;
+}
+
+extern "C" fn on_terminate(_: c_int) {
+    TERMINATE.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_relayout(_: c_int) {
+    RELAYOUT.store(true, Ordering::SeqCst);
+}
+
+/**
+ * Catch SIGINT and SIGTERM instead of dying right away
+ */
+pub fn catch_terminate() {
+    // SAFETY: the handler only stores into an atomic, which is async-signal-safe
+    unsafe {
+        signal(SIGINT, on_terminate);
+        signal(SIGTERM, on_terminate);
+    }
+}
+
+/**
+ * Whether we were asked to terminate since catch_terminate() was called
+ */
+pub fn terminate_requested() -> bool {
+    TERMINATE.load(Ordering::SeqCst)
+}
+
+/**
+ * Make SIGUSR2 request a relayout of the focused workspace, where we know its number
+ */
+pub fn catch_relayout() {
+    if let Some(signum) = SIGUSR2 {
+        // SAFETY: the handler only stores into an atomic, which is async-signal-safe
+        unsafe {
+            signal(signum, on_relayout);
+        }
+    }
+}
+
+/**
+ * Whether SIGUSR2 was received since the last call
+ */
+pub fn relayout_requested() -> bool {
+    RELAYOUT.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sigusr2_requests_a_relayout() {
+        // raising it anyway would end the tests
+        let signum = match SIGUSR2 {
+            Some(signum) => signum,
+            None => return,
+        };
+        catch_relayout();
+        assert!(!relayout_requested());
+        // SAFETY: the handler for it is installed
+        assert_eq!(unsafe { raise(signum) }, 0);
+        assert!(relayout_requested());
+        assert!(!relayout_requested());
+    }
+}
@@ -0,0 +1,361 @@
+//! The decisions for a recording instead of a live sway, see --simulate. A recording is a JSON
+//! Lines file, every line holds a window event together with the tree sway reported for it:
+//! `{"time": 1.5, "event": "focus", "tree": {...}}`. The event is about the focused window of
+//! the tree, or about the one with the id in `container` if the line has one.
+//!
+//! The events go through the daemon's own handling, against a fake sway that reports the
+//! recorded trees and on a clock that jumps from one event to the next. What falls due in
+//! between, e.g. at the end of --new-window-grace-ms, happens on the tree of the event before.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use swayipc::{Connection, Event};
+
+use autotiling_rs::clock::ManualClock;
+use autotiling_rs::config::Settings;
+
+use crate::fake_sway::FakeSway;
+use crate::shell::RecordingShell;
+use crate::{handle_event, relayout_all_on_start, run_timers, State, Timers};
+
+/// One line of a recording
+#[derive(Deserialize)]
+struct Record {
+    /// seconds since the recording started
+    time: f64,
+    /// the change of the window event, e.g. `focus` or `new`
+    event: String,
+    /// the id of the window the event is about, the focused one if not given
+    #[serde(default)]
+    container: Option<i64>,
+    tree: Value,
+}
+
+/**
+ * Write a line with the decision and the reason for it for every event of the recording to
+ * `out`, and for everything that happened in between
+ */
+pub fn run(settings: &Settings, path: &Path, out: &mut impl Write) -> Result<(), String> {
+    let records = read(path)?;
+    let first = match records.first() {
+        Some((_, record)) => record.tree.clone(),
+        None => return Ok(()),
+    };
+
+    let (sway, conn) = FakeSway::start(first);
+    let state = State::new(
+        Box::new(ManualClock::new()),
+        Box::new(RecordingShell::default()),
+    );
+    let start = state.clock.now();
+    let mut simulation = Simulation {
+        settings,
+        timers: Timers::new(settings, start),
+        start,
+        sway,
+        conn,
+        state,
+        out,
+    };
+    simulation.state.connected(&mut simulation.conn, settings);
+
+    for (number, record) in records {
+        let event = window_event(&record)
+            .map_err(|err| format!("{}:{}: {}", path.display(), number, err))?;
+        let at = start + Duration::from_secs_f64(record.time.max(0.0));
+        simulation.advance(at)?;
+
+        simulation.sway.set_tree(record.tree);
+        let before = Snapshot::take(&simulation);
+        relayout_all_on_start(&mut simulation.conn, settings, &mut simulation.state);
+        if let Some((decision, reason)) = before.since(&simulation) {
+            simulation.line(record.time, "start", &decision, &reason)?;
+        }
+
+        let before = Snapshot::take(&simulation);
+        simulation.state.stats.events += 1;
+        handle_event(&mut simulation.conn, settings, &mut simulation.state, event);
+        let (decision, reason) = before
+            .since(&simulation)
+            .unwrap_or_else(|| ("-".to_string(), "not acted on".to_string()));
+        simulation.line(record.time, &record.event, &decision, &reason)?;
+    }
+
+    // what the last events left waiting
+    while let Some(deadline) = simulation.state.next_deadline() {
+        simulation.advance(deadline)?;
+    }
+    Ok(())
+}
+
+/**
+ * The records of the recording at `path` with their line numbers
+ */
+fn read(path: &Path) -> Result<Vec<(usize, Record)>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|err| format!("{}:{}: {}", path.display(), number + 1, err))?;
+        records.push((number + 1, record));
+    }
+    Ok(records)
+}
+
+/**
+ * The window event the daemon would have gotten for `record`
+ */
+fn window_event(record: &Record) -> Result<Event, String> {
+    let container = match record.container {
+        Some(id) => find(&record.tree, &|node| node["id"] == id)
+            .ok_or_else(|| format!("there is no #{} in the tree", id))?,
+        None => find(&record.tree, &|node| node["focused"] == true)
+            .ok_or("nothing in the tree has focus")?,
+    };
+    let event = json!({ "change": record.event, "container": container });
+    let event = serde_json::from_value(event).map_err(|err| err.to_string())?;
+    Ok(Event::Window(Box::new(event)))
+}
+
+fn find<'a>(node: &'a Value, matches: &dyn Fn(&Value) -> bool) -> Option<&'a Value> {
+    if matches(node) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(|child| find(child, matches))
+}
+
+/// The daemon against a fake sway
+struct Simulation<'a, W> {
+    settings: &'a Settings,
+    timers: Timers,
+    /// when the recording started
+    start: Instant,
+    sway: FakeSway,
+    conn: Connection,
+    state: State,
+    out: &'a mut W,
+}
+
+impl<W: Write> Simulation<'_, W> {
+    /**
+     * Let the clock run up to `until`, doing what falls due on the way
+     */
+    fn advance(&mut self, until: Instant) -> Result<(), String> {
+        while let Some(due) = self
+            .timers
+            .next(self.settings, &self.state)
+            .filter(|&due| due <= until)
+        {
+            let now = self.state.clock.now();
+            self.state.clock.sleep(due.saturating_duration_since(now));
+            let before = Snapshot::take(self);
+            run_timers(
+                &mut self.conn,
+                self.settings,
+                &mut self.state,
+                &mut self.timers,
+                false,
+            )?;
+            if let Some((decision, reason)) = before.since(self) {
+                let time = due.duration_since(self.start).as_secs_f64();
+                self.line(time, "timer", &decision, &reason)?;
+            }
+        }
+        let now = self.state.clock.now();
+        self.state.clock.sleep(until.saturating_duration_since(now));
+        Ok(())
+    }
+
+    fn line(&mut self, time: f64, event: &str, decision: &str, reason: &str) -> Result<(), String> {
+        writeln!(
+            self.out,
+            "{:>9.3}  {:<8} {:<8} {}",
+            time, event, decision, reason
+        )
+        .map_err(|err| format!("could not write the timeline: {}", err))
+    }
+}
+
+/// What the daemon did so far, as far as the fake sway and the stats can tell
+struct Snapshot {
+    messages: usize,
+    tree_requests: usize,
+    relayouts: BTreeMap<&'static str, u64>,
+    skips: BTreeMap<&'static str, u64>,
+    deadline: Option<Instant>,
+}
+
+impl Snapshot {
+    fn take<W>(simulation: &Simulation<W>) -> Snapshot {
+        let state = &simulation.state;
+        Snapshot {
+            messages: simulation.sway.messages().len(),
+            tree_requests: simulation.sway.tree_requests(),
+            relayouts: state.stats.relayouts().clone(),
+            skips: state.stats.skips().clone(),
+            deadline: state.next_deadline(),
+        }
+    }
+
+    /**
+     * The decision since the snapshot was taken and the reason for it, None if the daemon did
+     * not even look
+     */
+    fn since<W>(&self, simulation: &Simulation<W>) -> Option<(String, String)> {
+        let state = &simulation.state;
+        let now = state.clock.now();
+        if let Some(layout) = increased(&self.relayouts, state.stats.relayouts()) {
+            let sent = simulation.sway.messages()[self.messages..].join("; ");
+            return Some((layout.to_string(), format!("sent `{}`", sent)));
+        }
+        if let Some(reason) = increased(&self.skips, state.stats.skips()) {
+            return Some(("skip".to_string(), reason.to_string()));
+        }
+        match state.next_deadline() {
+            Some(deadline) if self.deadline != Some(deadline) => {
+                let wait = deadline.saturating_duration_since(now);
+                return Some((
+                    "wait".to_string(),
+                    format!("looking again in {}ms", wait.as_millis()),
+                ));
+            }
+            _ => {}
+        }
+        if simulation.sway.tree_requests() == self.tree_requests {
+            return None;
+        }
+        let waiting = state
+            .before_new
+            .as_ref()
+            .is_some_and(|pending| !pending.commands.is_empty());
+        Some(if waiting {
+            (
+                "wait".to_string(),
+                "until a new window opens next to it".to_string(),
+            )
+        } else {
+            (
+                "keep".to_string(),
+                "the layout is right already".to_string(),
+            )
+        })
+    }
+}
+
+/// The first key that counts more in `after` than in `before`
+fn increased(
+    before: &BTreeMap<&'static str, u64>,
+    after: &BTreeMap<&'static str, u64>,
+) -> Option<&'static str> {
+    after
+        .iter()
+        .find(|(key, &count)| before.get(*key).copied().unwrap_or(0) < count)
+        .map(|(&key, _)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, desktop, focus, settings, window};
+
+    /// What --simulate prints for a recording of `records`, as (time, event, tree)
+    fn simulate(args: &[&str], records: &[(f64, &str, Value)]) -> String {
+        let path = env::temp_dir().join(format!(
+            "autotiling-rs-simulate-{}-{}.jsonl",
+            process::id(),
+            args.join("")
+        ));
+        let lines: Vec<String> = records
+            .iter()
+            .map(|(time, event, tree)| {
+                json!({ "time": time, "event": event, "tree": tree }).to_string()
+            })
+            .collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let mut out = Vec::new();
+        let result = run(&settings(args), &path, &mut out);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// The time, the event and the decision of every line of the timeline
+    fn timeline(args: &[&str], records: &[(f64, &str, Value)]) -> Vec<String> {
+        simulate(args, records)
+            .lines()
+            .map(|line| {
+                line.split_whitespace()
+                    .take(3)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn two_events_make_a_timeline() {
+        let tall = focus(
+            desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+            10,
+        );
+        // the new window #11 next to #10, in a workspace that is split vertically already
+        let wide = focus(
+            desktop(vec![window(10, 1000, 300), window(11, 1000, 300)]),
+            11,
+        );
+        let wide = testing::set(wide, 3, "layout", json!("splitv"));
+        let records = [(0.5, "focus", tall), (1.25, "new", wide)];
+
+        assert_eq!(
+            timeline(&[], &records),
+            ["0.500 focus splitv", "1.250 new -"]
+        );
+        // the decision for the focused #10 waits for the new window
+        assert_eq!(
+            timeline(&["--relayout-only-before-new"], &records),
+            ["0.500 focus wait", "1.250 new splitv"]
+        );
+        // once the grace period is over, the new window is laid out
+        assert_eq!(
+            timeline(&["--new-window-grace-ms", "200"], &records),
+            ["0.500 focus splitv", "1.250 new wait", "1.450 timer splith"]
+        );
+    }
+
+    #[test]
+    fn reasons_come_from_the_daemon() {
+        let tall = focus(
+            desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+            10,
+        );
+        let records = [(0.0, "focus", tall)];
+        assert_eq!(
+            simulate(&["--workspace", "2"], &records),
+            "    0.000  focus    skip     workspace not in --workspace\n"
+        );
+        assert_eq!(
+            simulate(&[], &records),
+            "    0.000  focus    splitv   sent `splitv`\n"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/**
+ * Counters describing what the daemon did during this session
+ */
+pub struct Stats {
+    started: Instant,
+    pub events: u64,
+    /// issued relayout commands by layout name
+    relayouts: BTreeMap<&'static str, u64>,
+    /// skipped decisions by reason
+    skips: BTreeMap<&'static str, u64>,
+    pub errors: u64,
+    pub reconnects: u64,
+}
+
+impl Stats {
+    /// A session that started at `started`
+    pub fn new(started: Instant) -> Stats {
+        Stats {
+            started,
+            events: 0,
+            relayouts: BTreeMap::new(),
+            skips: BTreeMap::new(),
+            errors: 0,
+            reconnects: 0,
+        }
+    }
+
+    pub fn relayout(&mut self, layout: &'static str) {
+        *self.relayouts.entry(layout).or_default() += 1;
+    }
+
+    pub fn skip(&mut self, reason: &'static str) {
+        *self.skips.entry(reason).or_default() += 1;
+    }
+
+    /// How often every layout was set so far
+    pub fn relayouts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.relayouts
+    }
+
+    /// How often every reason kept us from acting so far
+    pub fn skips(&self) -> &BTreeMap<&'static str, u64> {
+        &self.skips
+    }
+
+    /**
+     * The counters as of `now`, for a line of output
+     */
+    pub fn summary(&self, now: Instant) -> Summary<'_> {
+        Summary {
+            stats: self,
+            uptime: now.saturating_duration_since(self.started),
+        }
+    }
+}
+
+pub struct Summary<'a> {
+    stats: &'a Stats,
+    uptime: Duration,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Summary { stats, uptime } = self;
+        let uptime = uptime.as_secs();
+        write!(
+            f,
+            "uptime {}h{:02}m{:02}s, {} events, relayouts:",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60,
+            stats.events
+        )?;
+        for layout in ["splith", "splitv", "tabbed"] {
+            write!(
+                f,
+                " {} {}",
+                stats.relayouts.get(layout).unwrap_or(&0),
+                layout
+            )?;
+        }
+        write!(f, ", skipped: {}", stats.skips.values().sum::<u64>())?;
+        if !stats.skips.is_empty() {
+            let reasons: Vec<String> = stats
+                .skips
+                .iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            write!(f, " ({})", reasons.join(", "))?;
+        }
+        write!(
+            f,
+            ", {} ipc errors, {} reconnects",
+            stats.errors, stats.reconnects
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let started = Instant::now();
+        let mut stats = Stats::new(started);
+        stats.events = 7;
+        stats.relayout("splitv");
+        stats.relayout("splith");
+        stats.relayout("splitv");
+        stats.skip("same window focused again");
+        stats.skip("ratio within the deadzone");
+        stats.skip("same window focused again");
+        stats.errors = 1;
+        stats.reconnects = 2;
+        let now = started + Duration::from_secs(2 * 3600 + 5 * 60 + 9);
+        assert_eq!(
+            stats.summary(now).to_string(),
+            "uptime 2h05m09s, 7 events, relayouts: 1 splith 2 splitv 0 tabbed, skipped: 3 \
+             (1 ratio within the deadzone, 2 same window focused again), 1 ipc errors, 2 reconnects"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! The mode specific part of the decision. Every `--mode` is a [`Strategy`], the checks that
+//! apply in every mode are done before a strategy is asked.
+
+use std::convert::TryFrom;
+
+use swayipc::{Node, NodeLayout, NodeType};
+
+use crate::cli::{Layout, Mode, TieBreak};
+use crate::config::Settings;
+use crate::layout::{aspect_layout, get_parent, tiling_leaves, Decision, Trace, RATIO_EPSILON};
+
+/// Everything a strategy may base its decision on
+pub struct DecisionContext<'a> {
+    pub tree: &'a Node,
+    /// the window the decision is for
+    pub focused: &'a Node,
+    /// the container whose layout is decided
+    pub parent: &'a Node,
+    pub workspace: Option<&'a Node>,
+    pub output: Option<&'a Node>,
+    /// width and height of the measured container
+    pub size: (i32, i32),
+    /// height/width of the measured container
+    pub real_ratio: f32,
+    /// the threshold for `real_ratio`
+    pub ratio: f32,
+    pub settings: &'a Settings,
+}
+
+pub trait Strategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision;
+}
+
+/**
+ * The strategy implementing `mode`
+ */
+pub fn strategy(mode: Mode) -> &'static dyn Strategy {
+    match mode {
+        Mode::Ratio => &RatioStrategy,
+        Mode::Alternate => &AlternateStrategy,
+        Mode::Spiral => &SpiralStrategy,
+        Mode::MasterStack => &MasterStackStrategy,
+        Mode::BspParent => &BspParentStrategy,
+    }
+}
+
+/// Compare the aspect ratio with the threshold
+pub struct RatioStrategy;
+
+impl Strategy for RatioStrategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision {
+        let DecisionContext {
+            parent,
+            size: (width, height),
+            real_ratio,
+            ratio,
+            settings,
+            ..
+        } = *ctx;
+
+        // a fixed layout by the number of windows, for those who prefer that to the shape
+        let children = parent.nodes.len();
+        if let Some(count) = settings.splith_below_count {
+            let below = children < count;
+            trace.step(|| {
+                format!(
+                    "{} children below --splith-below-count {}: {}",
+                    children, count, below
+                )
+            });
+            if below {
+                return Decision::Layout(NodeLayout::SplitH);
+            }
+        }
+        if let Some(count) = settings.splitv_above_count {
+            let above = children > count;
+            trace.step(|| {
+                format!(
+                    "{} children above --splitv-above-count {}: {}",
+                    children, count, above
+                )
+            });
+            if above {
+                return Decision::Layout(NodeLayout::SplitV);
+            }
+        }
+
+        // very wide containers are better off tabbed than split even further
+        let parent_ratio = (parent.rect.width as f32) / (parent.rect.height as f32);
+        if let Some(threshold) = settings.tabbed_threshold_ratio {
+            let wide = parent.rect.height > 0 && parent_ratio > threshold;
+            trace.step(|| {
+                format!(
+                    "parent width/height {:.3} above --tabbed-threshold-ratio {}: {}",
+                    parent_ratio, threshold, wide
+                )
+            });
+            if wide {
+                return Decision::Layout(NodeLayout::Tabbed);
+            }
+        }
+
+        // without a size there is no ratio to look at, aspect_layout starts out like sway does
+        if width <= 0 || height <= 0 {
+            return Decision::Layout(aspect_layout(
+                unsigned(width),
+                unsigned(height),
+                ratio,
+                false,
+            ));
+        }
+
+        // near-square windows flip between the layouts too easily, leave them alone
+        if let Some((low, high)) = settings.deadzone {
+            let inside = (low..=high).contains(&real_ratio);
+            trace.step(|| format!("ratio within the deadzone {}..={}: {}", low, high, inside));
+            if inside {
+                return Decision::Skip("ratio within the deadzone");
+            }
+        }
+
+        if let Some(curve) = &settings.ratio_curve {
+            // below the first breakpoint the first layout applies as well
+            let layout = curve
+                .iter()
+                .rev()
+                .find(|(from, _)| real_ratio >= *from)
+                .unwrap_or(&curve[0])
+                .1;
+            trace.step(|| {
+                format!(
+                    "ratio {:.3} maps to {:?} on --ratio-curve",
+                    real_ratio, layout
+                )
+            });
+            return Decision::Layout(node_layout(layout));
+        }
+
+        if (real_ratio - ratio).abs() <= RATIO_EPSILON {
+            trace.step(|| {
+                format!(
+                    "ratio equals the threshold, --tie-break {:?}",
+                    settings.tie_break
+                )
+            });
+            return match settings.tie_break {
+                TieBreak::Keep => Decision::Skip("ratio equals the threshold"),
+                TieBreak::Splith => Decision::Layout(NodeLayout::SplitH),
+                TieBreak::Splitv => Decision::Layout(NodeLayout::SplitV),
+            };
+        }
+
+        trace.step(|| {
+            format!(
+                "ratio {:.3} above the threshold {}: {}",
+                real_ratio,
+                ratio,
+                real_ratio > ratio
+            )
+        });
+        // --reverse is applied to whatever the strategy decided
+        Decision::Layout(aspect_layout(
+            unsigned(width),
+            unsigned(height),
+            ratio,
+            false,
+        ))
+    }
+}
+
+/// Sizes below zero are as good as no size
+fn unsigned(length: i32) -> u32 {
+    u32::try_from(length).unwrap_or_default()
+}
+
+fn node_layout(layout: Layout) -> NodeLayout {
+    match layout {
+        Layout::Splith => NodeLayout::SplitH,
+        Layout::Splitv => NodeLayout::SplitV,
+        Layout::Tabbed => NodeLayout::Tabbed,
+    }
+}
+
+/// Split the parent container along its longer side, whatever --ratio and --ratio-target say
+pub struct BspParentStrategy;
+
+impl Strategy for BspParentStrategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision {
+        // the plan measured the parent against 1 for this mode
+        let (width, height) = ctx.size;
+        trace.step(|| {
+            format!(
+                "ratio {:.3} of the parent #{} above {}: {}",
+                ctx.real_ratio,
+                ctx.parent.id,
+                ctx.ratio,
+                ctx.real_ratio > ctx.ratio
+            )
+        });
+        Decision::Layout(aspect_layout(
+            unsigned(width),
+            unsigned(height),
+            ctx.ratio,
+            false,
+        ))
+    }
+}
+
+/// Flip the split direction with every level of nesting
+pub struct AlternateStrategy;
+
+impl Strategy for AlternateStrategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision {
+        // flip the direction of the container the parent lives in
+        let outer =
+            get_parent(ctx.tree, ctx.parent).filter(|_| ctx.parent.node_type == NodeType::Con);
+        trace.step(|| {
+            format!(
+                "surrounding container layout {:?}",
+                outer.map(|n| &n.layout)
+            )
+        });
+        match outer.map(|n| &n.layout) {
+            Some(NodeLayout::SplitH) => Decision::Layout(NodeLayout::SplitV),
+            _ => Decision::Layout(NodeLayout::SplitH),
+        }
+    }
+}
+
+/// Flip the split direction with every window on the workspace
+pub struct SpiralStrategy;
+
+impl Strategy for SpiralStrategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision {
+        split_by_index(ctx, trace, |index| index % 2 == 0)
+    }
+}
+
+/// The first window on the left, all others stacked on the right of it
+pub struct MasterStackStrategy;
+
+impl Strategy for MasterStackStrategy {
+    fn decide(&self, ctx: &DecisionContext, trace: &mut Trace) -> Decision {
+        split_by_index(ctx, trace, |index| index == 0)
+    }
+}
+
+/**
+ * Split horizontally if `horizontal` says so for the position of the focused window among
+ * the windows of the workspace, vertically otherwise
+ */
+fn split_by_index(
+    ctx: &DecisionContext,
+    trace: &mut Trace,
+    horizontal: impl Fn(usize) -> bool,
+) -> Decision {
+    let leaves = ctx.workspace.map(tiling_leaves).unwrap_or_default();
+    let index = leaves
+        .iter()
+        .position(|leaf| leaf.id == ctx.focused.id)
+        .unwrap_or_default();
+    trace.step(|| format!("window {} of {} on the workspace", index + 1, leaves.len()));
+    if horizontal(index) {
+        Decision::Layout(NodeLayout::SplitH)
+    } else {
+        Decision::Layout(NodeLayout::SplitV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swayipc::NodeLayout;
+
+    use super::*;
+    use crate::layout::find_node;
+    use crate::testing::{decision, desktop, focus, tree, window};
+
+    /// Two 960x300 windows side by side on a 1920x1080 workspace, #10 is focused
+    fn two_windows() -> serde_json::Value {
+        focus(
+            desktop(vec![window(10, 960, 300), window(11, 960, 300)]),
+            10,
+        )
+    }
+
+    /// The focused #10 of `width` x `height` next to another window
+    fn focused(width: i32, height: i32) -> serde_json::Value {
+        focus(
+            desktop(vec![window(10, width, height), window(11, 960, 1080)]),
+            10,
+        )
+    }
+
+    #[test]
+    fn tabbed_threshold_ratio() {
+        // the workspace the windows are in is 1920/1080 = 1.778 wide
+        assert_eq!(
+            decision(&two_windows(), &["--tabbed-threshold-ratio", "1.77"]),
+            Decision::Layout(NodeLayout::Tabbed)
+        );
+        assert_eq!(
+            decision(&two_windows(), &["--tabbed-threshold-ratio", "1.78"]),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn deadzone() {
+        let args = ["--deadzone-low", "0.8", "--deadzone-high", "1.2"];
+        let inside = Decision::Skip("ratio within the deadzone");
+        assert_eq!(decision(&focused(500, 500), &args), inside);
+        assert_eq!(decision(&focused(500, 400), &args), inside);
+        assert_eq!(decision(&focused(500, 600), &args), inside);
+        assert_eq!(
+            decision(&focused(500, 1000), &args),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        assert_eq!(
+            decision(&focused(1000, 300), &args),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn strategies_differ_on_the_same_context() {
+        // the focused #10 is tall, but the first window of the workspace
+        let tree = tree(focus(
+            desktop(vec![window(10, 640, 1080), window(11, 1280, 1080)]),
+            10,
+        ));
+        let find = |id| find_node(&tree, id).unwrap();
+        let settings = Settings::default();
+        let ctx = DecisionContext {
+            tree: &tree,
+            focused: find(10),
+            parent: find(3),
+            workspace: Some(find(3)),
+            output: Some(find(2)),
+            size: (640, 1080),
+            real_ratio: 1080.0 / 640.0,
+            ratio: settings.ratio,
+            settings: &settings,
+        };
+        let decide = |mode| strategy(mode).decide(&ctx, &mut Trace::default());
+
+        assert_eq!(decide(Mode::Ratio), Decision::Layout(NodeLayout::SplitV));
+        assert_eq!(decide(Mode::Spiral), Decision::Layout(NodeLayout::SplitH));
+        assert_eq!(
+            decide(Mode::MasterStack),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+        // the plan measures the workspace for it, which is wider than tall
+        let bsp = DecisionContext {
+            size: (1920, 1080),
+            real_ratio: 1080.0 / 1920.0,
+            ratio: 1.0,
+            ..ctx
+        };
+        assert_eq!(
+            strategy(Mode::BspParent).decide(&bsp, &mut Trace::default()),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+
+    #[test]
+    fn ratio_curve() {
+        let args = ["--ratio-curve", "0.3:splitv,0.8:splith,2.5:tabbed"];
+        // ratios below the first breakpoint, within each bucket and on a breakpoint
+        for (height, layout) in [
+            (100, NodeLayout::SplitV),
+            (150, NodeLayout::SplitV),
+            (300, NodeLayout::SplitV),
+            (500, NodeLayout::SplitH),
+            (1250, NodeLayout::Tabbed),
+            (2000, NodeLayout::Tabbed),
+        ] {
+            assert_eq!(
+                decision(&focused(500, height), &args),
+                Decision::Layout(layout),
+                "500x{}",
+                height
+            );
+        }
+    }
+
+    /// `count` windows of 100x200 next to each other, the first one is focused
+    fn windows(count: i64) -> serde_json::Value {
+        let windows = (0..count).map(|i| window(10 + i, 100, 200)).collect();
+        focus(desktop(windows), 10)
+    }
+
+    #[test]
+    fn child_counts() {
+        let args = ["--splith-below-count", "3", "--splitv-above-count", "5"];
+        // by their ratio, the windows would be split vertically
+        assert_eq!(
+            decision(&windows(2), &args),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+        for count in 3..=5 {
+            assert_eq!(
+                decision(&windows(count), &args),
+                Decision::Layout(NodeLayout::SplitV),
+                "{} windows",
+                count
+            );
+        }
+        assert_eq!(
+            decision(&windows(6), &["--splitv-above-count", "5", "--ratio", "5"]),
+            Decision::Layout(NodeLayout::SplitV)
+        );
+        // in between, the ratio decides
+        assert_eq!(
+            decision(&windows(4), &[&args[..], &["--ratio", "5"]].concat()),
+            Decision::Layout(NodeLayout::SplitH)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Trees and events for the tests, built as the JSON sway sends since swayipc's types can not be
+//! constructed directly. Shared by the tests of the library, of the daemon and of the command
+//! line, so not every crate uses every helper.
+#![allow(dead_code)]
+
+use std::iter;
+
+use clap::Parser;
+use serde_json::{json, Value};
+use swayipc::{Event, Node, Output};
+
+use autotiling_rs::cli::Cli;
+use autotiling_rs::config::{FileConfig, Settings};
+use autotiling_rs::layout::{decide_layout, Decision, Trace};
+use autotiling_rs::outputs::Outputs;
+
+/**
+ * The command line `args`, without the program name. clap falls back to $AUTOTILING_RATIO and
+ * $AUTOTILING_MODE, but whoever runs the tests may have set them, so only `args` count here.
+ */
+pub fn cli(args: &[&str]) -> Cli {
+    let mut cli = Cli::try_parse_from(iter::once("autotiling-rs").chain(args.iter().copied()))
+        .expect("invalid arguments");
+    let given = |long: &str, short: &str| {
+        args.iter().any(|arg| {
+            arg.strip_prefix(long)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+                || (!short.is_empty() && arg.starts_with(short))
+        })
+    };
+    if !given("--ratio", "-r") {
+        cli.ratio = None;
+    }
+    if !given("--mode", "") {
+        cli.mode = None;
+    }
+    cli
+}
+
+/**
+ * The settings for the command line `args`, without any config file
+ */
+pub fn settings(args: &[&str]) -> Settings {
+    Settings::resolve(&cli(args), FileConfig::default()).expect("invalid settings")
+}
+
+/**
+ * The decision for the focused window of `tree` with the command line `args`
+ */
+pub fn decision(tree: &Value, args: &[&str]) -> Decision {
+    let tree = self::tree(tree.clone());
+    decide_layout(
+        &tree,
+        &settings(args),
+        &Outputs::default(),
+        &mut Trace::default(),
+    )
+    .expect("no decision")
+    .decision
+}
+
+fn rect(width: i32, height: i32) -> Value {
+    json!({ "x": 0, "y": 0, "width": width, "height": height })
+}
+
+/**
+ * A node of any type without children
+ */
+pub fn node(id: i64, node_type: &str, layout: &str, width: i32, height: i32) -> Value {
+    json!({
+        "id": id,
+        "name": null,
+        "type": node_type,
+        "border": "none",
+        "current_border_width": 0,
+        "layout": layout,
+        "percent": null,
+        "rect": rect(width, height),
+        "window_rect": rect(width, height),
+        "deco_rect": rect(0, 0),
+        "geometry": rect(width, height),
+        "urgent": false,
+        "focused": false,
+        "focus": [],
+        "nodes": [],
+        "floating_nodes": [],
+        "sticky": false,
+        "marks": [],
+    })
+}
+
+/**
+ * A tiling window with the app_id `app`
+ */
+pub fn window(id: i64, width: i32, height: i32) -> Value {
+    let mut window = node(id, "con", "none", width, height);
+    window["app_id"] = json!("app");
+    window["percent"] = json!(0.5);
+    window
+}
+
+/**
+ * A split container holding `children`
+ */
+pub fn split(id: i64, layout: &str, width: i32, height: i32, children: Vec<Value>) -> Value {
+    let mut split = node(id, "con", layout, width, height);
+    split["nodes"] = Value::Array(children);
+    split
+}
+
+/**
+ * Workspace `num`, of the same size as the output it is on in `desktop`
+ */
+pub fn workspace(id: i64, num: i32, layout: &str, children: Vec<Value>) -> Value {
+    let mut workspace = node(id, "workspace", layout, 1920, 1080);
+    workspace["num"] = json!(num);
+    workspace["name"] = json!(num.to_string());
+    workspace["nodes"] = Value::Array(children);
+    workspace
+}
+
+pub fn output(id: i64, name: &str, width: i32, height: i32, workspaces: Vec<Value>) -> Value {
+    let mut output = node(id, "output", "output", width, height);
+    output["name"] = json!(name);
+    output["nodes"] = Value::Array(workspaces);
+    output
+}
+
+pub fn root(outputs: Vec<Value>) -> Value {
+    let mut root = node(1, "root", "splith", 1920, 1080);
+    root["name"] = json!("root");
+    root["nodes"] = Value::Array(outputs);
+    root
+}
+
+/**
+ * A 1920x1080 output eDP-1 with workspace 1 holding `children` in a horizontal split
+ */
+pub fn desktop(children: Vec<Value>) -> Value {
+    root(vec![output(
+        2,
+        "eDP-1",
+        1920,
+        1080,
+        vec![workspace(3, 1, "splith", children)],
+    )])
+}
+
+/**
+ * Focus the node with `id` the way sway does: it is focused and every node on the way to it
+ * lists the child leading there first in its focus stack
+ */
+pub fn focus(mut tree: Value, id: i64) -> Value {
+    assert!(focus_path(&mut tree, id), "no node #{} to focus", id);
+    tree
+}
+
+fn focus_path(node: &mut Value, id: i64) -> bool {
+    if node["id"] == json!(id) {
+        node["focused"] = json!(true);
+        return true;
+    }
+    for children in ["nodes", "floating_nodes"] {
+        let found = match node[children].as_array_mut() {
+            Some(children) => children
+                .iter_mut()
+                .find_map(|child| focus_path(child, id).then(|| child["id"].clone())),
+            None => None,
+        };
+        if let Some(child) = found {
+            let mut focus = vec![child.clone()];
+            for other in node["nodes"].as_array().into_iter().flatten() {
+                if other["id"] != child {
+                    focus.push(other["id"].clone());
+                }
+            }
+            node["focus"] = Value::Array(focus);
+            return true;
+        }
+    }
+    false
+}
+
+/**
+ * Set `key` to `value` on the node with `id`
+ */
+pub fn set(mut tree: Value, id: i64, key: &str, value: Value) -> Value {
+    let node = find(&mut tree, id).unwrap_or_else(|| panic!("no node #{}", id));
+    node[key] = value;
+    tree
+}
+
+fn find(node: &mut Value, id: i64) -> Option<&mut Value> {
+    if node["id"] == json!(id) {
+        return Some(node);
+    }
+    let children = node.as_object_mut()?;
+    let mut found = None;
+    for (key, value) in children.iter_mut() {
+        if key == "nodes" || key == "floating_nodes" {
+            for child in value.as_array_mut()? {
+                if found.is_none() {
+                    found = find(child, id);
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn tree(value: Value) -> Node {
+    serde_json::from_value(value).expect("not a valid tree")
+}
+
+/**
+ * A window event about the window with `id`, e.g. `focus` or `new`
+ */
+pub fn window_event(change: &str, id: i64) -> Event {
+    let event = json!({ "change": change, "container": window(id, 100, 100) });
+    Event::Window(Box::new(
+        serde_json::from_value(event).expect("not a window event"),
+    ))
+}
+
+pub fn mode_event(mode: &str) -> Event {
+    let event = json!({ "change": mode, "pango_markup": false });
+    Event::Mode(serde_json::from_value(event).expect("not a mode event"))
+}
+
+pub fn binding_event(command: &str) -> Event {
+    let event = json!({
+        "change": "run",
+        "binding": {
+            "command": command,
+            "event_state_mask": [],
+            "input_code": 0,
+            "symbol": null,
+            "input_type": "keyboard",
+        },
+    });
+    Event::Binding(serde_json::from_value(event).expect("not a binding event"))
+}
+
+pub fn workspace_event(change: &str, current: Option<Value>) -> Event {
+    let event = json!({ "change": change, "current": current, "old": null });
+    Event::Workspace(Box::new(
+        serde_json::from_value(event).expect("not a workspace event"),
+    ))
+}
+
+/**
+ * An output as get_outputs() reports it
+ */
+pub fn output_info(name: &str, width: i32, height: i32, scale: f64) -> Value {
+    json!({
+        "id": 1,
+        "name": name,
+        "make": "",
+        "model": "",
+        "serial": "",
+        "active": true,
+        "dpms": true,
+        "primary": false,
+        "scale": scale,
+        "subpixel_hinting": null,
+        "transform": "normal",
+        "current_workspace": null,
+        "modes": [],
+        "current_mode": {
+            "width": (width as f64 * scale).round() as i32,
+            "height": (height as f64 * scale).round() as i32,
+            "refresh": 60000,
+        },
+        "rect": rect(width, height),
+    })
+}
+
+/**
+ * Outputs as known after get_outputs() reported `outputs`, see output_info()
+ */
+pub fn outputs(outputs: Vec<Value>) -> Outputs {
+    let outputs: Vec<Output> =
+        serde_json::from_value(Value::Array(outputs)).expect("not a list of outputs");
+    let mut known = Outputs::default();
+    known.update(outputs);
+    known
+}
@@ -0,0 +1,1062 @@
+//! Tests of the daemon against a fake sway, see fake_sway.rs
+
+use autotiling_rs::clock::ManualClock;
+
+use serde_json::json;
+
+use super::*;
+use crate::fake_sway::FakeSway;
+use crate::shell::{RecordingShell, Spawned};
+use crate::testing::{
+    self, binding_event, desktop, focus, mode_event, output_info, settings, split, window,
+    window_event, workspace_event,
+};
+
+/**
+ * A state on a manual clock, together with what its shell was asked to run
+ */
+fn state() -> (State, Spawned) {
+    let shell = RecordingShell::default();
+    let spawned = shell.0.clone();
+    (
+        State::new(Box::new(ManualClock::new()), Box::new(shell)),
+        spawned,
+    )
+}
+
+/// Two windows side by side, the focused #10 is taller than wide
+fn two_tall_windows() -> serde_json::Value {
+    focus(
+        desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+        10,
+    )
+}
+
+#[test]
+fn relayout_hook_gets_the_decision_in_its_environment() {
+    let settings = settings(&["--on-relayout", "notify"]);
+    let (mut state, spawned) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+
+    assert_eq!(sway.commands(), ["splitv"]);
+    let spawned = spawned.borrow();
+    assert_eq!(spawned.len(), 1);
+    let (command, env) = &spawned[0];
+    assert_eq!(command, "notify");
+    let env: Vec<(&str, &str)> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    assert_eq!(
+        env,
+        [
+            ("AT_LAYOUT", "splitv"),
+            ("AT_WORKSPACE", "1"),
+            ("AT_APPID", "app")
+        ]
+    );
+}
+
+#[test]
+fn failed_connection_is_an_error() {
+    let err = match open_connection(Path::new("/nonexistent/sway.sock"), None) {
+        Ok(_) => panic!("connected to nothing"),
+        Err(err) => err,
+    };
+    assert!(
+        err.starts_with("could not connect to sway at \"/nonexistent/sway.sock\" (is it running?)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn self_test_stops_after_the_first_focus_event() {
+    let settings = settings(&[]);
+    let (_sway, mut conn) = FakeSway::start(two_tall_windows());
+    let events = vec![
+        Ok(window_event("new", 10)),
+        Ok(window_event("focus", 10)),
+        Ok(window_event("focus", 11)),
+    ];
+    let mut out = Vec::new();
+
+    self_test(&mut conn, events, &settings, &mut out).unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "connected to sway, waiting for a focus event...");
+    assert!(lines[1].starts_with("focused: #10 "), "{}", out);
+    assert_eq!(lines[3], "ratio:   2.500 (threshold 0.4)");
+    assert_eq!(lines[4], "layout:  splitv");
+    assert_eq!(lines.len(), 5);
+}
+
+#[test]
+fn poll_decides_without_an_event() {
+    let settings = settings(&["--poll-ms", "100"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    state.clock.sleep(Duration::from_millis(99));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert!(sway.commands().is_empty());
+
+    state.clock.sleep(Duration::from_millis(1));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv"]);
+
+    // and again one interval later
+    state.clock.sleep(Duration::from_millis(100));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv", "splitv"]);
+}
+
+#[test]
+fn split_target_outermost_climbs_to_below_the_workspace() {
+    // workspace > #20 > #21 > #22 > focused #10
+    let nested = split(
+        20,
+        "splith",
+        1200,
+        1080,
+        vec![
+            split(
+                21,
+                "splitv",
+                1200,
+                1000,
+                vec![
+                    split(
+                        22,
+                        "splith",
+                        800,
+                        1000,
+                        vec![window(10, 400, 1000), window(11, 400, 1000)],
+                    ),
+                    window(12, 400, 1000),
+                ],
+            ),
+            window(13, 1200, 80),
+        ],
+    );
+    let tree = focus(desktop(vec![nested, window(14, 720, 1080)]), 10);
+
+    for (target, command) in [("parent", "splitv"), ("outermost", "[con_id=20] splitv")] {
+        let settings = settings(&["--split-target", target]);
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(tree.clone());
+        switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+        assert_eq!(sway.commands(), [command], "--split-target {}", target);
+    }
+}
+
+#[test]
+fn paused_while_in_a_listed_mode() {
+    let settings = settings(&["--pause-in-modes", "resize,move"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+
+    handle_event(&mut conn, &settings, &mut state, mode_event("resize"));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert!(sway.commands().is_empty());
+
+    handle_event(&mut conn, &settings, &mut state, mode_event("default"));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert_eq!(sway.commands(), ["splitv"]);
+}
+
+#[test]
+fn tie_keeps_the_layout() {
+    // 200/500 is exactly the default ratio
+    let tree = focus(
+        desktop(vec![window(10, 500, 200), window(11, 500, 200)]),
+        10,
+    );
+    for (tie_break, commands) in [("keep", vec![]), ("splitv", vec!["splitv"])] {
+        let settings = settings(&["--tie-break", tie_break]);
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(tree.clone());
+        switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+        assert_eq!(sway.commands(), commands, "--tie-break {}", tie_break);
+    }
+}
+
+#[test]
+fn output_changes_update_the_auto_ratio() {
+    let settings = settings(&["--auto-ratio"]);
+    let (mut state, _) = state();
+    // a 800x640 window has a ratio of 0.8
+    let tree = focus(
+        desktop(vec![window(10, 800, 640), window(11, 800, 640)]),
+        10,
+    );
+    let (sway, mut conn) = FakeSway::start(tree.clone());
+    let tree = testing::tree(tree);
+    let decide = |outputs: &Outputs| {
+        decide_layout(&tree, &settings, outputs, &mut Trace::default())
+            .unwrap()
+            .decision
+    };
+
+    sway.set_outputs(vec![output_info("eDP-1", 1920, 1080, 1.0)]);
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        workspace_event("init", None),
+    );
+    assert_eq!(decide(&state.outputs), Decision::Layout(NodeLayout::SplitV));
+
+    // the output was rotated
+    sway.set_outputs(vec![output_info("eDP-1", 1080, 1920, 1.0)]);
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        workspace_event("reload", None),
+    );
+    assert_eq!(decide(&state.outputs), Decision::Layout(NodeLayout::SplitH));
+
+    // keeping track of the outputs does not relayout anything
+    assert!(sway.commands().is_empty());
+}
+
+#[test]
+fn split_hint_forces_the_direction_once() {
+    // by its ratio, the window would be split horizontally
+    let tree = focus(
+        desktop(vec![window(10, 960, 200), window(11, 960, 200)]),
+        10,
+    );
+    let tree = testing::set(tree, 10, "marks", json!(["at:splitv"]));
+    let settings = settings(&[]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(tree);
+
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+
+    assert_eq!(sway.commands(), ["splitv", "[con_id=10] unmark at:splitv"]);
+}
+
+#[test]
+fn swallowing_app_defers_the_decision() {
+    let settings = settings(&["--swallow-apps", "^app$", "--swallow-delay-ms", "100"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+    assert!(sway.commands().is_empty());
+    assert_eq!(
+        state.deferred,
+        Some(state.clock.now() + Duration::from_millis(100))
+    );
+
+    state.clock.sleep(Duration::from_millis(100));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv"]);
+    assert_eq!(state.deferred, None);
+
+    // without a delay, they are left alone
+    let settings = self::settings(&["--swallow-apps", "^app$"]);
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+    assert_eq!(state.deferred, None);
+    assert_eq!(sway.commands(), ["splitv"]);
+}
+
+#[test]
+fn single_window_override_can_be_disabled() {
+    let tree = focus(desktop(vec![window(10, 1920, 1080)]), 10);
+    let tree = testing::set(tree, 3, "layout", json!("splitv"));
+    for (args, commands) in [
+        (vec![], vec!["splith"]),
+        (vec!["--no-single-window-override"], vec![]),
+    ] {
+        let settings = settings(&args);
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(tree.clone());
+        switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+        assert_eq!(sway.commands(), commands, "{:?}", args);
+    }
+}
+
+#[test]
+fn warmup_ignores_the_first_events_after_connecting() {
+    let settings = settings(&["--warmup-events", "2"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    state.connected(&mut conn, &settings);
+
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 11));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert!(sway.commands().is_empty());
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert_eq!(sway.commands(), ["splitv"]);
+
+    // reconnecting starts over
+    state.connected(&mut conn, &settings);
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 11));
+    assert_eq!(sway.commands(), ["splitv"]);
+}
+
+#[test]
+fn requested_relayout_lays_out_every_window_of_the_workspace() {
+    let tall = split(
+        20,
+        "splith",
+        800,
+        1000,
+        vec![window(10, 400, 1000), window(11, 400, 1000)],
+    );
+    let tree = focus(desktop(vec![tall, window(12, 1120, 300)]), 12);
+    let settings = settings(&[]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(tree);
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    run_timers(&mut conn, &settings, &mut state, &mut timers, true).unwrap();
+
+    // the focused #12 is wide and already split horizontally
+    assert_eq!(
+        sway.commands(),
+        ["[con_id=10] splitv", "[con_id=11] splitv"]
+    );
+}
+
+#[test]
+fn stale_tree_is_fetched_again() {
+    // right after the window appeared, it has no size yet
+    let stale = focus(desktop(vec![window(10, 0, 0), window(11, 400, 1000)]), 10);
+    let settings = settings(&["--stale-retry-ms", "20"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(stale);
+    sway.then(two_tall_windows());
+    let start = state.clock.now();
+
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+
+    assert_eq!(sway.commands(), ["splitv"]);
+    assert_eq!(state.clock.now() - start, Duration::from_millis(20));
+
+    // a tree that looks fine is not fetched again
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+    assert_eq!(state.clock.now() - start, Duration::from_millis(20));
+}
+
+#[test]
+fn uptime_follows_the_clock_of_the_state() {
+    let (state, _) = state();
+    state.clock.sleep(Duration::from_secs(3600 + 65));
+    let summary = state.stats.summary(state.clock.now()).to_string();
+    assert!(
+        summary.starts_with("uptime 1h01m05s, 0 events"),
+        "{}",
+        summary
+    );
+}
+
+#[test]
+fn workspace_parent_is_compared_by_its_reported_layout() {
+    let workspace = |layout| testing::tree(testing::workspace(3, 1, layout, vec![]));
+
+    // sway reports the layout the tiling children of the workspace are arranged in
+    assert_eq!(
+        layout_command(&NodeLayout::SplitH, &workspace("splitv"), None),
+        Some("splith".to_string())
+    );
+    assert_eq!(
+        layout_command(&NodeLayout::SplitV, &workspace("tabbed"), Some(3)),
+        Some("[con_id=3] splitv".to_string())
+    );
+    assert_eq!(
+        layout_command(&NodeLayout::SplitV, &workspace("splitv"), None),
+        None
+    );
+}
+
+#[test]
+fn echo_of_our_own_command_is_ignored() {
+    // so that only the echo check stands between focus events for the same window
+    let settings = settings(&["--no-act-on-own-commands", "--no-skip-same-focus"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert_eq!(sway.commands(), ["splitv"]);
+    // sway reporting the window we just split
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert_eq!(sway.commands(), ["splitv"]);
+
+    state.clock.sleep(OWN_COMMAND_ECHO);
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    assert_eq!(sway.commands(), ["splitv", "splitv"]);
+}
+
+#[test]
+fn resize_relayouts_once_it_settled() {
+    let settings = settings(&["--relayout-on-resize"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        binding_event("resize grow width 10px"),
+    );
+    state.clock.sleep(Duration::from_millis(149));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert!(sway.commands().is_empty());
+
+    state.clock.sleep(Duration::from_millis(1));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv"]);
+
+    // other bindings are not waited for
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        binding_event("focus left"),
+    );
+    state.clock.sleep(Duration::from_millis(150));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv"]);
+}
+
+#[test]
+fn minimal_log_lines() {
+    let settings = settings(&[]);
+    let tree = testing::tree(two_tall_windows());
+    let mut plan =
+        decide_layout(&tree, &settings, &Outputs::default(), &mut Trace::default()).unwrap();
+    assert_eq!(
+        decision_line(LogFormat::Minimal, &plan),
+        "2.500 0.400 splitv"
+    );
+
+    plan.decision = Decision::Skip("focused again within --per-container-ms");
+    assert_eq!(
+        decision_line(LogFormat::Minimal, &plan),
+        "2.500 0.400 focused-again-within-per-container-ms"
+    );
+    // what is skipped without a decision is logged as `- - <token>`
+    assert_eq!(
+        skip_token("mode in --pause-in-modes"),
+        "mode-in-pause-in-modes"
+    );
+}
+
+#[test]
+fn double_check_warns_about_an_ignored_command() {
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    // the tree of the fake sway stays as it is, whatever it is told
+    assert_eq!(
+        double_check(&mut conn, 10, "splitv").unwrap(),
+        Some("asked for splitv around #10, but sway reports splith".to_string())
+    );
+    assert_eq!(
+        double_check(&mut conn, 42, "splitv").unwrap(),
+        Some("asked for splitv around #42, but sway reports no such container".to_string())
+    );
+    assert_eq!(double_check(&mut conn, 10, "splith").unwrap(), None);
+
+    // the check comes after the command
+    let settings = settings(&["--double-check"]);
+    let (mut state, _) = state();
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+    assert_eq!(sway.commands(), ["splitv"]);
+}
+
+#[test]
+fn relayout_pass_is_sent_in_a_single_message() {
+    let tall = split(
+        20,
+        "splith",
+        800,
+        1000,
+        vec![window(10, 400, 1000), window(11, 400, 1000)],
+    );
+    let tree = focus(desktop(vec![tall, window(12, 1120, 300)]), 12);
+    let relayout = |args: &[&str]| {
+        let settings = settings(args);
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(tree.clone());
+        relayout_workspaces(&mut conn, &settings, &mut state, false).unwrap();
+        sway.messages()
+    };
+
+    assert_eq!(
+        relayout(&["--batch-commands", "--state-file", "/nonexistent/state"]),
+        ["[con_id=10] splitv; [con_id=10] mark --add at:state:10; \
+          [con_id=11] splitv; [con_id=11] mark --add at:state:11"]
+    );
+    // without batching, the commands for a window still go together
+    assert_eq!(
+        relayout(&["--state-file", "/nonexistent/state"]),
+        [
+            "[con_id=10] splitv; [con_id=10] mark --add at:state:10",
+            "[con_id=11] splitv; [con_id=11] mark --add at:state:11"
+        ]
+    );
+}
+
+#[test]
+fn focus_is_restored_after_the_split_moved_it() {
+    let settings = settings(&["--preserve-focus"]);
+    let moved = focus(
+        desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+        11,
+    );
+    for (after, commands) in [
+        (moved, vec!["splitv", "[con_id=10] focus"]),
+        (two_tall_windows(), vec!["splitv"]),
+    ] {
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(two_tall_windows());
+        sway.then(after);
+        switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+        assert_eq!(sway.commands(), commands);
+    }
+}
+
+#[test]
+fn every_suppression_reason_is_explained() {
+    let settings = settings(&[
+        "--explain-startup",
+        "--pause-in-modes",
+        "resize",
+        "--workspace",
+        "2",
+    ]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+
+    for _ in 0..2 {
+        handle_event(&mut conn, &settings, &mut state, mode_event("resize"));
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+        handle_event(&mut conn, &settings, &mut state, mode_event("default"));
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    }
+
+    assert!(sway.commands().is_empty());
+    let mut explained: Vec<&str> = state.explained.iter().copied().collect();
+    explained.sort_unstable();
+    assert_eq!(
+        explained,
+        [
+            "mode in --pause-in-modes",
+            "same window focused again",
+            "workspace not in --workspace"
+        ]
+    );
+}
+
+#[test]
+fn failing_keep_alive_is_an_error() {
+    let settings = settings(&["--keep-alive-ms", "100"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    state.clock.sleep(Duration::from_millis(100));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+
+    sway.hang_up();
+    state.clock.sleep(Duration::from_millis(100));
+    let err = run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap_err();
+    assert!(err.starts_with("sway stopped answering: "), "{}", err);
+
+    // the command connection waits no longer than the keep-alive interval for an answer
+    assert_eq!(command_timeout(&settings), Some(Duration::from_millis(100)));
+    let settings = self::settings(&["--keep-alive-ms", "100", "--ipc-timeout-ms", "50"]);
+    assert_eq!(command_timeout(&settings), Some(Duration::from_millis(50)));
+    assert_eq!(command_timeout(&self::settings(&[])), None);
+}
+
+#[test]
+fn shutting_down_the_event_socket_ends_the_reader() {
+    let settings = settings(&[]);
+    let (mut state, _) = state();
+    let (_sway, mut conn) = FakeSway::start(two_tall_windows());
+    let (_events_sway, socket) = FakeSway::listen(two_tall_windows());
+    let events_socket = socket.try_clone().unwrap();
+    let events = Connection::from(socket)
+        .subscribe([EventType::Window])
+        .unwrap();
+    let events = spawn_event_reader(events);
+
+    events_socket.shutdown(Shutdown::Both).unwrap();
+
+    let err = handle_events(&mut conn, &events, &settings, &mut state).unwrap_err();
+    assert!(err.starts_with("lost connection to sway: "), "{}", err);
+    // and the reader thread is gone
+    assert!(matches!(
+        events.recv_timeout(Duration::from_secs(5)),
+        Err(RecvTimeoutError::Disconnected)
+    ));
+}
+
+#[test]
+fn lost_connection_is_reconnected_until_sway_stays_away() {
+    let settings = settings(&[]);
+    let (mut state, _) = state();
+    let focus_10 = json!({ "change": "focus", "container": window(10, 400, 1000) });
+    // two sways that each go away after a focus event, and then none at all
+    let mut sways = Vec::new();
+    let mut connections = Vec::new();
+    for _ in 0..2 {
+        let (sway, conn) = FakeSway::start(two_tall_windows());
+        let (events_sway, socket) = FakeSway::listen(two_tall_windows());
+        events_sway.stream(vec![focus_10.clone()]);
+        sways.push(sway);
+        connections.push((conn, socket));
+    }
+    let mut connections = connections.into_iter();
+    let mut attempts = 0;
+
+    let err = run(&settings, &mut state, || {
+        attempts += 1;
+        match connections.next() {
+            Some((conn, socket)) => subscribe(&settings, conn, socket),
+            None => Err("could not connect to sway".to_string()),
+        }
+    })
+    .unwrap_err();
+
+    assert_eq!(err, "giving up after 5 attempts");
+    // each connection handled its event, the second one after the first one was lost
+    assert_eq!(sways[0].commands(), ["splitv"]);
+    assert_eq!(sways[1].commands(), ["splitv"]);
+    assert_eq!(attempts, 6);
+    assert_eq!(state.stats.reconnects, 5);
+    assert_eq!(state.stats.events, 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn paused_while_the_screen_is_locked() {
+    // the test itself stands in for the locker
+    let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    for (locker, commands) in [
+        (comm.trim_end(), vec![]),
+        ("no-such-locker", vec!["splitv"]),
+    ] {
+        let settings = settings(&["--pause-when-locked", "--lock-app", locker]);
+        let (mut state, _) = state();
+        assert_eq!(
+            state.paused(&settings).is_some(),
+            commands.is_empty(),
+            "{}",
+            locker
+        );
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+        assert_eq!(sway.commands(), commands, "{}", locker);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lock_state_is_looked_up_once_per_interval() {
+    let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+    let locked = settings(&["--pause-when-locked", "--lock-app", comm.trim_end()]);
+    // stands in for the locker having exited
+    let unlocked = settings(&["--pause-when-locked", "--lock-app", "no-such-locker"]);
+    let (mut state, _) = state();
+
+    assert!(state.paused(&locked).is_some());
+    state
+        .clock
+        .sleep(LOCK_CHECK_INTERVAL - Duration::from_millis(1));
+    assert!(state.paused(&unlocked).is_some());
+    state.clock.sleep(Duration::from_millis(1));
+    assert_eq!(state.paused(&unlocked), None);
+}
+
+#[test]
+fn unhandled_layout_is_warned_about_once() {
+    let tree = testing::set(two_tall_windows(), 3, "layout", json!("spiral"));
+    let (_sway, mut conn) = FakeSway::start(tree);
+    let reported = Cell::new(false);
+    let mut warn = || unreadable_tree_warning(&conn.get_tree().unwrap_err(), &reported);
+
+    let warning = warn().expect("no warning");
+    assert!(
+        warning.starts_with("sway sent a tree this version does not understand"),
+        "{}",
+        warning
+    );
+    assert!(warning.contains("spiral"), "{}", warning);
+    assert_eq!(warn(), None);
+}
+
+#[test]
+fn layout_without_a_rule_is_warned_about_once() {
+    // swayipc knows the layout, but no window is ever in a container with it
+    let tree = testing::tree(testing::set(
+        two_tall_windows(),
+        3,
+        "layout",
+        json!("output"),
+    ));
+    let settings = settings(&[]);
+    let plan = decide_layout(&tree, &settings, &Outputs::default(), &mut Trace::default()).unwrap();
+    let reported = Cell::new(false);
+
+    let warning = unhandled_tree_warning(&plan, &reported).expect("no warning");
+    assert_eq!(
+        warning,
+        "sway sent the layout Output on #3, which this version does not handle, is sway newer than autotiling-rs?"
+    );
+    assert_eq!(unhandled_tree_warning(&plan, &reported), None);
+
+    let tree = testing::tree(two_tall_windows());
+    let plan = decide_layout(&tree, &settings, &Outputs::default(), &mut Trace::default()).unwrap();
+    assert_eq!(unhandled_tree_warning(&plan, &Cell::new(false)), None);
+}
+
+#[test]
+fn remembered_layouts_survive_a_restart() {
+    let path = env::temp_dir().join(format!("autotiling-rs-state-{}.json", process::id()));
+    let path = path.to_str().unwrap();
+    let settings = settings(&["--state-file", path]);
+
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    switch_splitting(&mut conn, &settings, &mut state, false).unwrap();
+    assert_eq!(
+        sway.commands(),
+        ["splitv", "[con_id=10] mark --add at:state:10"]
+    );
+    save_state(&settings, &state);
+
+    // after the restart, #10 carries the mark and #11 is remembered as laid out already
+    let tree = testing::set(two_tall_windows(), 10, "marks", json!(["at:state:10"]));
+    let tree = testing::set(tree, 11, "marks", json!(["at:state:11"]));
+    let (mut state, _) = self::state();
+    state.remembered = persist::load(Path::new(path)).unwrap();
+    state
+        .remembered
+        .insert("at:state:11".to_string(), "splith".to_string());
+    state
+        .remembered
+        .insert("at:state:12".to_string(), "splitv".to_string());
+    let (sway, mut conn) = FakeSway::start(tree);
+    state.connected(&mut conn, &settings);
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        sway.commands(),
+        ["[con_mark=\"at:state:10\"] layout splitv"]
+    );
+    // #12 is gone, and so is #11 once it is closed
+    handle_event(&mut conn, &settings, &mut state, window_event("close", 11));
+    let remembered: Vec<&str> = state.remembered.keys().map(String::as_str).collect();
+    assert_eq!(remembered, ["at:state:10"]);
+}
+
+#[test]
+fn focusing_the_same_window_again_skips_the_tree() {
+    // #10 is already split vertically, so nothing is issued in between
+    let tree = testing::set(two_tall_windows(), 3, "layout", json!("splitv"));
+    for (args, requests) in [(vec![], 1), (vec!["--no-skip-same-focus"], 2)] {
+        let settings = settings(&args);
+        let (mut state, _) = state();
+        let (sway, mut conn) = FakeSway::start(tree.clone());
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+        assert_eq!(sway.tree_requests(), requests, "{:?}", args);
+        assert!(sway.commands().is_empty());
+    }
+}
+
+#[test]
+fn per_container_ms_throttles_a_single_window() {
+    // so that only the throttling stands between focus events for the same window
+    let settings = settings(&["--per-container-ms", "500", "--no-skip-same-focus"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut focus = |state: &mut State, id| {
+        handle_event(&mut conn, &settings, state, window_event("focus", id));
+        sway.commands().len()
+    };
+
+    assert_eq!(focus(&mut state, 10), 1);
+    assert_eq!(focus(&mut state, 10), 1);
+    state.clock.sleep(Duration::from_millis(499));
+    assert_eq!(focus(&mut state, 10), 1);
+    // #11 was not looked at yet
+    assert_eq!(focus(&mut state, 11), 2);
+
+    state.clock.sleep(Duration::from_millis(1));
+    assert_eq!(focus(&mut state, 10), 3);
+    assert_eq!(focus(&mut state, 11), 3);
+}
+
+/// Workspaces 1 and 2 with two tall windows each, #10 and #11 on the first, #20 and #21 on the
+/// second
+fn two_workspaces() -> serde_json::Value {
+    let windows = |first| vec![window(first, 400, 1000), window(first + 1, 400, 1000)];
+    testing::root(vec![testing::output(
+        2,
+        "eDP-1",
+        1920,
+        1080,
+        vec![
+            testing::workspace(3, 1, "splith", windows(10)),
+            testing::workspace(4, 2, "splith", windows(20)),
+        ],
+    )])
+}
+
+/**
+ * Lay out #10 on workspace 1 and then #20 on workspace 2, the marks they got remembered by
+ */
+fn lay_out_both_workspaces(settings: &Settings, state: &mut State) -> Vec<String> {
+    let (sway, mut conn) = FakeSway::start(focus(two_workspaces(), 10));
+    switch_splitting(&mut conn, settings, state, false).unwrap();
+    sway.set_tree(focus(two_workspaces(), 20));
+    switch_splitting(&mut conn, settings, state, false).unwrap();
+    state.remembered.keys().cloned().collect()
+}
+
+#[test]
+fn removed_workspace_is_forgotten() {
+    let settings = settings(&["--state-file", "unused.json", "--container-layout-cache"]);
+    let (mut state, _) = state();
+    assert_eq!(
+        lay_out_both_workspaces(&settings, &mut state),
+        ["at:state:10", "at:state:20"]
+    );
+
+    let (_sway, mut conn) = FakeSway::start(focus(two_workspaces(), 20));
+    let emptied = testing::workspace(3, 1, "splith", vec![]);
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        workspace_event("empty", Some(emptied)),
+    );
+    let remembered: Vec<&str> = state.remembered.keys().map(String::as_str).collect();
+    assert_eq!(remembered, ["at:state:20"]);
+    assert!(!state.layouts.contains_key(&10));
+    assert!(state.layouts.contains_key(&20));
+}
+
+#[test]
+fn least_recently_laid_out_workspace_makes_room() {
+    let settings = settings(&[
+        "--state-file",
+        "unused.json",
+        "--max-workspaces-tracked",
+        "1",
+    ]);
+    let (mut state, _) = state();
+    assert_eq!(
+        lay_out_both_workspaces(&settings, &mut state),
+        ["at:state:20"]
+    );
+}
+
+#[test]
+fn relayout_all_on_start_covers_every_workspace_once_unpaused() {
+    let tree = two_workspaces();
+    let settings = settings(&["--relayout-all-on-start", "--pause-in-modes", "resize"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(focus(tree, 10));
+
+    state.mode = "resize".to_string();
+    relayout_all_on_start(&mut conn, &settings, &mut state);
+    assert!(sway.messages().is_empty());
+
+    state.mode = "default".to_string();
+    relayout_all_on_start(&mut conn, &settings, &mut state);
+    relayout_all_on_start(&mut conn, &settings, &mut state);
+    assert_eq!(
+        sway.messages(),
+        ["splitv; [con_id=11] splitv; [con_id=20] splitv; [con_id=21] splitv"]
+    );
+}
+
+#[test]
+fn piling_up_errors_notify_once() {
+    let settings = settings(&["--notify-on-error", "--notify-command", "notify"]);
+    let (mut state, spawned) = state();
+
+    for i in 0..NOTIFY_AFTER_ERRORS * 3 {
+        state.report(&settings, &format!("error {}", i));
+        state.clock.sleep(Duration::from_secs(1));
+    }
+    assert_eq!(
+        *spawned.borrow(),
+        [(
+            "notify".to_string(),
+            vec![(
+                "AT_ERROR".to_string(),
+                format!("error {}", NOTIFY_AFTER_ERRORS - 1)
+            )]
+        )]
+    );
+
+    // until a while later
+    state.clock.sleep(NOTIFY_INTERVAL);
+    state.report(&settings, "again");
+    assert_eq!(spawned.borrow().len(), 1);
+    for _ in 1..NOTIFY_AFTER_ERRORS {
+        state.report(&settings, "again");
+    }
+    assert_eq!(spawned.borrow().len(), 2);
+}
+
+#[test]
+fn new_window_is_decided_on_the_tree_after_the_grace_period() {
+    let settings = settings(&["--new-window-grace-ms", "100"]);
+    let (mut state, _) = state();
+    // the new #12 starts out wide
+    let transient = focus(
+        desktop(vec![window(11, 1000, 300), window(12, 920, 100)]),
+        12,
+    );
+    let (sway, mut conn) = FakeSway::start(transient);
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    handle_event(&mut conn, &settings, &mut state, window_event("new", 12));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 12));
+    state.clock.sleep(Duration::from_millis(99));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert!(sway.commands().is_empty());
+
+    // then it took its size, and the focus went back to #11 meanwhile
+    sway.set_tree(focus(
+        desktop(vec![window(11, 1000, 300), window(12, 400, 1000)]),
+        11,
+    ));
+    state.clock.sleep(Duration::from_millis(1));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["[con_id=12] splitv"]);
+    assert_eq!(sway.tree_requests(), 1);
+}
+
+#[test]
+fn windows_opened_within_one_grace_period_are_all_laid_out() {
+    let settings = settings(&["--new-window-grace-ms", "100"]);
+    let (mut state, _) = state();
+    let tree = focus(
+        desktop(vec![window(11, 400, 1000), window(12, 400, 1000)]),
+        12,
+    );
+    let (sway, mut conn) = FakeSway::start(tree);
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    handle_event(&mut conn, &settings, &mut state, window_event("new", 11));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 11));
+    state.clock.sleep(Duration::from_millis(50));
+    handle_event(&mut conn, &settings, &mut state, window_event("new", 12));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 12));
+    assert!(sway.commands().is_empty());
+
+    state.clock.sleep(Duration::from_millis(50));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["[con_id=11] splitv"]);
+
+    state.clock.sleep(Duration::from_millis(50));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert_eq!(sway.commands(), ["[con_id=11] splitv", "splitv"]);
+}
+
+#[test]
+fn slow_sway_times_out() {
+    let settings = settings(&["--ipc-timeout-ms", "50", "--poll-ms", "100"]);
+    let (mut state, _) = state();
+    let (sway, socket) = FakeSway::listen(two_tall_windows());
+    socket.set_read_timeout(command_timeout(&settings)).unwrap();
+    let mut conn = Connection::from(socket);
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    sway.set_delay(Duration::from_millis(500));
+    state.clock.sleep(Duration::from_millis(100));
+    let err = run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap_err();
+
+    assert_eq!(err, "sway did not answer within 50ms");
+    assert!(sway.commands().is_empty());
+}
+
+#[test]
+fn only_a_new_window_relayouts_with_relayout_only_before_new() {
+    let settings = settings(&[
+        "--relayout-only-before-new",
+        "--poll-ms",
+        "100",
+        "--relayout-on-resize",
+    ]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut timers = Timers::new(&settings, state.clock.now());
+
+    for id in [11, 10, 11, 10] {
+        handle_event(&mut conn, &settings, &mut state, window_event("focus", id));
+    }
+    handle_event(
+        &mut conn,
+        &settings,
+        &mut state,
+        binding_event("resize shrink height 10px"),
+    );
+    state.clock.sleep(Duration::from_millis(200));
+    run_timers(&mut conn, &settings, &mut state, &mut timers, false).unwrap();
+    assert!(sway.messages().is_empty());
+
+    handle_event(&mut conn, &settings, &mut state, window_event("new", 12));
+    assert_eq!(sway.commands(), ["[con_id=10] splitv"]);
+}
+
+#[test]
+fn new_window_gets_the_decision_for_the_window_it_opened_next_to() {
+    let settings = settings(&["--relayout-only-before-new"]);
+    let (mut state, _) = state();
+    // #10 is tall and #11 wide in a vertical split
+    let tree = |focused, children| {
+        let tree = testing::set(desktop(children), 3, "layout", json!("splitv"));
+        focus(tree, focused)
+    };
+    let (sway, mut conn) =
+        FakeSway::start(tree(10, vec![window(10, 400, 1000), window(11, 1000, 300)]));
+
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 10));
+    sway.set_tree(tree(11, vec![window(10, 400, 1000), window(11, 1000, 300)]));
+    handle_event(&mut conn, &settings, &mut state, window_event("focus", 11));
+    assert!(sway.messages().is_empty());
+
+    // #12 opens next to #11 and takes the focus
+    sway.set_tree(tree(
+        12,
+        vec![
+            window(10, 400, 1000),
+            window(11, 1000, 150),
+            window(12, 1000, 150),
+        ],
+    ));
+    handle_event(&mut conn, &settings, &mut state, window_event("new", 12));
+    assert_eq!(sway.commands(), ["[con_id=11] splith"]);
+}
+
+#[test]
+fn layout_cache_suppresses_a_repeat() {
+    // the fake tree never shows our command, so each focus event would issue it again
+    let settings = settings(&["--container-layout-cache", "--no-skip-same-focus"]);
+    let (mut state, _) = state();
+    let (sway, mut conn) = FakeSway::start(two_tall_windows());
+    let mut focus = |state: &mut State| {
+        handle_event(&mut conn, &settings, state, window_event("focus", 10));
+        sway.commands().len()
+    };
+
+    assert_eq!(focus(&mut state), 1);
+    assert_eq!(focus(&mut state), 1);
+    state.clock.sleep(LAYOUT_CACHE_TTL);
+    assert_eq!(focus(&mut state), 2);
+}
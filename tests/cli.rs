@@ -0,0 +1,208 @@
+//! Runs of the binary that need no sway
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+#[path = "../src/testing.rs"]
+mod testing;
+
+use testing::{desktop, focus, window};
+
+/**
+ * autotiling-rs with `args`, without any settings from the environment or a config file
+ */
+fn autotiling(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_autotiling-rs"));
+    command
+        .args(args)
+        .env_remove("AUTOTILING_MODE")
+        .env_remove("AUTOTILING_RATIO")
+        .env(
+            "AUTOTILING_SYSTEM_CONFIG",
+            config_home("none").join("system.toml"),
+        )
+        .env("XDG_CONFIG_HOME", config_home("none"));
+    command
+}
+
+/**
+ * A config directory of its own for `test`
+ */
+fn config_home(test: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(test)
+}
+
+/**
+ * A config directory with `config` as the user config file
+ */
+fn user_config(test: &str, config: &str) -> PathBuf {
+    let home = config_home(test);
+    fs::create_dir_all(home.join("autotiling-rs")).unwrap();
+    fs::write(home.join("autotiling-rs").join("config.toml"), config).unwrap();
+    home
+}
+
+#[test]
+fn missing_sway_is_a_clean_error() {
+    let output = autotiling(&["--self-test"])
+        .env("SWAYSOCK", "/nonexistent/sway.sock")
+        .env_remove("I3SOCK")
+        .output()
+        .expect("could not run autotiling-rs");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("err: could not connect to sway at \"/nonexistent/sway.sock\""),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn check_config() {
+    let home = user_config("check_config", "ratio = 0.6\nratio-target = \"parent\"\n");
+    let output = autotiling(&["--check-config", "--mode", "spiral"])
+        .env("XDG_CONFIG_HOME", &home)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ratio: 0.6,"), "{}", stdout);
+    assert!(stdout.contains("ratio_target: Parent,"), "{}", stdout);
+    assert!(stdout.contains("mode: Spiral,"), "{}", stdout);
+}
+
+#[test]
+fn completions_are_printed_without_looking_at_the_config() {
+    let home = user_config("completions", "no-such-option = true\n");
+    for shell in ["bash", "zsh", "fish"] {
+        let output = autotiling(&["--generate-completions", shell])
+            .env("XDG_CONFIG_HOME", &home)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{}", shell);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("ratio-target"), "{}: {}", shell, stdout);
+    }
+}
+
+#[test]
+fn command_line_overrides_the_system_and_the_user_config() {
+    let home = user_config("layering", "ratio = 0.6\n");
+    let system = home.join("system.toml");
+    fs::write(&system, "ratio = 0.5\npoll-ms = 100\nreverse = true\n").unwrap();
+    let check_config = |args: &[&str]| {
+        let output = autotiling(args)
+            .env("AUTOTILING_SYSTEM_CONFIG", &system)
+            .env("XDG_CONFIG_HOME", &home)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let stdout = check_config(&["--check-config"]);
+    assert!(stdout.contains("ratio: 0.6,"), "{}", stdout);
+    assert!(stdout.contains("poll_ms: 100,"), "{}", stdout);
+    assert!(stdout.contains("reverse: true,"), "{}", stdout);
+
+    let stdout = check_config(&["--check-config", "--reverse=false", "--poll-ms", "0"]);
+    assert!(stdout.contains("ratio: 0.6,"), "{}", stdout);
+    assert!(stdout.contains("poll_ms: 0,"), "{}", stdout);
+    assert!(stdout.contains("reverse: false,"), "{}", stdout);
+}
+
+#[test]
+fn check_config_rejects_invalid_settings() {
+    let output = autotiling(&["--check-config", "--ratio=-1"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "err: ratio must be a positive number, got -1\n"
+    );
+
+    let home = user_config("check_config_invalid", "ratio = 0.6\nratoi = 1\n");
+    let output = autotiling(&["--check-config"])
+        .env("XDG_CONFIG_HOME", &home)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown field `ratoi`"), "{}", stderr);
+}
+
+#[test]
+fn environment_is_used_unless_a_flag_overrides_it() {
+    let output = autotiling(&["--check-config"])
+        .env("AUTOTILING_RATIO", "0.7")
+        .env("AUTOTILING_MODE", "spiral")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ratio: 0.7,"), "{}", stdout);
+    assert!(stdout.contains("mode: Spiral,"), "{}", stdout);
+
+    let output = autotiling(&["--check-config", "--ratio", "0.3"])
+        .env("AUTOTILING_RATIO", "0.7")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ratio: 0.3,"), "{}", stdout);
+}
+
+#[test]
+fn decision_for_a_piped_tree() {
+    let tree = focus(
+        desktop(vec![window(10, 400, 1000), window(11, 400, 1000)]),
+        10,
+    );
+    let why = |args: &[&str]| {
+        let mut child = autotiling(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(tree.to_string().as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(why(&["--from-stdin"]), "=> splitv on #3\n");
+    let steps = why(&["--from-stdin", "--why"]);
+    assert!(steps.starts_with("focused: #10 "), "{}", steps);
+    assert!(
+        steps.ends_with("ratio 2.500 above the threshold 0.4: true\n=> splitv on #3\n"),
+        "{}",
+        steps
+    );
+}
+
+#[test]
+fn piped_garbage_is_an_error() {
+    let mut child = autotiling(&["--from-stdin"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"{").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("err: could not read a tree from stdin: "),
+        "{}",
+        stderr
+    );
+}